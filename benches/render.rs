@@ -0,0 +1,112 @@
+//! Compares the old per-pixel `fill_rect` renderer with the streaming texture
+//! renderer used by `DisplayDriver`. Both draw a 64x32 frame scaled to the
+//! default 1280x640 window with SDL's software renderer, so no display is needed.
+//!
+//! Run with `cargo bench --bench render`.
+
+use std::time::{Duration, Instant};
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::surface::Surface;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+const SCALE: u32 = 20;
+const FRAMES: u32 = 500;
+
+fn main() -> Result<(), String> {
+    // Checkerboard-ish pattern so both colors are drawn
+    let mut vram = [[0u8; WIDTH]; HEIGHT];
+    for (y, row) in vram.iter_mut().enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = ((x / 3 + y / 2) % 2) as u8;
+        }
+    }
+
+    let fill_rect = bench(|canvas| {
+        for (y, row) in vram.iter().enumerate() {
+            for (x, &pixel) in row.iter().enumerate() {
+                canvas.set_draw_color(color(pixel));
+                canvas.fill_rect(Rect::new(
+                    x as i32 * SCALE as i32,
+                    y as i32 * SCALE as i32,
+                    SCALE,
+                    SCALE,
+                ))?;
+            }
+        }
+        Ok(())
+    })?;
+
+    let mut texture = None;
+    let streaming = bench(|canvas| {
+        if texture.is_none() {
+            texture = Some(
+                canvas
+                    .create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)
+                    .map_err(|e| e.to_string())?,
+            );
+        }
+        let texture = texture.as_mut().unwrap();
+
+        texture.with_lock(None, |buffer, pitch| {
+            for (y, row) in vram.iter().enumerate() {
+                for (x, &pixel) in row.iter().enumerate() {
+                    let c = color(pixel);
+                    let offset = y * pitch + x * 3;
+                    buffer[offset..offset + 3].copy_from_slice(&[c.r, c.g, c.b]);
+                }
+            }
+        })?;
+        canvas.copy(texture, None, None)
+    })?;
+
+    report("fill_rect per pixel", fill_rect);
+    report("streaming texture", streaming);
+    println!(
+        "streaming texture is {:.1}x faster",
+        fill_rect.as_secs_f64() / streaming.as_secs_f64()
+    );
+    Ok(())
+}
+
+/// Time `FRAMES` draws onto a window-sized software canvas.
+fn bench<F>(mut draw: F) -> Result<Duration, String>
+where
+    F: FnMut(&mut Canvas<Surface<'static>>) -> Result<(), String>,
+{
+    let surface = Surface::new(
+        WIDTH as u32 * SCALE,
+        HEIGHT as u32 * SCALE,
+        PixelFormatEnum::RGB888,
+    )?;
+    let mut canvas = surface.into_canvas()?;
+
+    // Warm up caches and lazily created resources
+    draw(&mut canvas)?;
+
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        draw(&mut canvas)?;
+        canvas.present();
+    }
+    Ok(start.elapsed())
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{:<20} {:>8.1} us/frame",
+        name,
+        elapsed.as_secs_f64() * 1e6 / FRAMES as f64
+    );
+}
+
+fn color(pixel: u8) -> Color {
+    if pixel == 0 {
+        Color::BLACK
+    } else {
+        Color::WHITE
+    }
+}
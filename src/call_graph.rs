@@ -0,0 +1,252 @@
+//! Subroutine call stacks tracked from 2nnn and 00EE, with instruction counts
+//! per stack for flame graphs and a timeline of calls.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::symbols::Symbols;
+
+// Calls kept for the timeline; later ones are counted but not recorded
+const MAX_TIMELINE_CALLS: usize = 1_000_000;
+
+struct Node {
+    function: Option<u16>, // None for the code outside any subroutine
+    parent: usize,
+    children: HashMap<u16, usize>, // Callee address to node
+    count: u64,                    // Instructions run with exactly this stack
+}
+
+/// A finished call, for the timeline.
+struct Call {
+    function: u16,
+    depth: usize,
+    start: u64,
+    end: u64,
+}
+
+/// Instruction counts per call stack, collected while a `Chip8` runs.
+pub struct CallGraph {
+    nodes: Vec<Node>,     // Call stack trie; node 0 is the root
+    current: usize,       // Node of the running stack
+    open_calls: Vec<u64>, // Start of each call on the running stack
+    calls: Vec<Call>,
+    dropped_calls: u64,             // Calls left out of the timeline
+    call_counts: HashMap<u16, u64>, // Calls made to each subroutine
+    instructions: u64,
+}
+
+impl CallGraph {
+    pub fn new() -> CallGraph {
+        CallGraph {
+            nodes: vec![Node {
+                function: None,
+                parent: 0,
+                children: HashMap::new(),
+                count: 0,
+            }],
+            current: 0,
+            open_calls: Vec::new(),
+            calls: Vec::new(),
+            dropped_calls: 0,
+            call_counts: HashMap::new(),
+            instructions: 0,
+        }
+    }
+
+    /// Count an instruction that continued at `next_pc`, following calls
+    /// and returns.
+    pub fn record(&mut self, opcode: u16, next_pc: u16) {
+        self.nodes[self.current].count += 1;
+        self.instructions += 1;
+
+        if opcode & 0xF000 == 0x2000 {
+            self.enter(next_pc);
+        } else if opcode == 0x00EE {
+            self.leave();
+        }
+    }
+
+    /// Unwind to the root, e.g. after the machine is reset.
+    pub fn restart(&mut self) {
+        while self.current != 0 {
+            self.leave();
+        }
+    }
+
+    fn enter(&mut self, function: u16) {
+        let next = self.nodes.len();
+        let current = self.current;
+        let child = *self.nodes[current].children.entry(function).or_insert(next);
+        if child == next {
+            self.nodes.push(Node {
+                function: Some(function),
+                parent: current,
+                children: HashMap::new(),
+                count: 0,
+            });
+        }
+        self.current = child;
+        self.open_calls.push(self.instructions);
+        *self.call_counts.entry(function).or_default() += 1;
+    }
+
+    fn leave(&mut self) {
+        // A return without a call leaves the stack as it is
+        let (Some(function), Some(start)) =
+            (self.nodes[self.current].function, self.open_calls.pop())
+        else {
+            return;
+        };
+
+        if self.calls.len() < MAX_TIMELINE_CALLS {
+            self.calls.push(Call {
+                function,
+                depth: self.open_calls.len(),
+                start,
+                end: self.instructions,
+            });
+        } else {
+            self.dropped_calls += 1;
+        }
+        self.current = self.nodes[self.current].parent;
+    }
+
+    /// Functions on the stack of a node, outermost first.
+    fn stack(&self, mut node: usize) -> Vec<u16> {
+        let mut stack = Vec::new();
+        while let Some(function) = self.nodes[node].function {
+            stack.push(function);
+            node = self.nodes[node].parent;
+        }
+        stack.reverse();
+        stack
+    }
+
+    /// Stacks in the folded format of flamegraph.pl and similar tools, one
+    /// `root;caller;callee count` line per stack.
+    pub fn folded(&self, entry_point: u16, symbols: &Symbols) -> String {
+        let root = root_name(entry_point, symbols);
+        let mut lines: Vec<String> = (0..self.nodes.len())
+            .filter(|&node| self.nodes[node].count > 0)
+            .map(|node| {
+                let mut line = root.clone();
+                for function in self.stack(node) {
+                    line.push(';');
+                    line.push_str(&function_name(function, symbols));
+                }
+                format!("{} {}", line, self.nodes[node].count)
+            })
+            .collect();
+        lines.sort();
+        lines.join("\n") + "\n"
+    }
+
+    /// Calls as Chrome trace events, for chrome://tracing or Perfetto. One
+    /// instruction is shown as one microsecond.
+    pub fn chrome_trace(&self, entry_point: u16, symbols: &Symbols) -> String {
+        let mut events = vec![event(
+            &root_name(entry_point, symbols),
+            0,
+            0,
+            self.instructions,
+        )];
+
+        // Calls still running when the program stopped end with the trace
+        let finished = self
+            .calls
+            .iter()
+            .map(|call| (call.function, call.depth, call.start, call.end));
+        let running = self.stack(self.current);
+        let unfinished = running
+            .into_iter()
+            .zip(self.open_calls.iter().copied())
+            .enumerate()
+            .map(|(depth, (function, start))| (function, depth, start, self.instructions));
+        for (function, depth, start, end) in finished.chain(unfinished) {
+            events.push(event(
+                &function_name(function, symbols),
+                depth + 1,
+                start,
+                end,
+            ));
+        }
+
+        let mut json = String::from("{\"traceEvents\":[\n");
+        json.push_str(&events.join(",\n"));
+        let _ = write!(
+            json,
+            "\n],\"displayTimeUnit\":\"ns\",\"otherData\":{{\"droppedCalls\":{}}}}}\n",
+            self.dropped_calls
+        );
+        json
+    }
+
+    /// Instructions per subroutine, counting callees (inclusive) and not
+    /// (exclusive), most expensive first.
+    pub fn report(&self, symbols: &Symbols) -> String {
+        let mut inclusive: HashMap<u16, u64> = HashMap::new();
+        let mut exclusive: HashMap<u16, u64> = HashMap::new();
+
+        for (index, node) in self.nodes.iter().enumerate() {
+            let mut stack = self.stack(index);
+            if let Some(&function) = stack.last() {
+                *exclusive.entry(function).or_default() += node.count;
+            }
+            // Recursive functions count once per stack
+            stack.sort_unstable();
+            stack.dedup();
+            for function in stack {
+                *inclusive.entry(function).or_default() += node.count;
+            }
+        }
+
+        let total = self.instructions.max(1) as f64;
+        let mut functions: Vec<_> = inclusive.into_iter().collect();
+        functions.sort_by_key(|&(function, count)| (std::cmp::Reverse(count), function));
+
+        let mut lines = vec![format!(
+            "Subroutines ({} instructions):\n  {:<24} {:>8} {:>22} {:>22}",
+            self.instructions, "", "calls", "inclusive", "exclusive"
+        )];
+        for (function, count) in functions {
+            let own = exclusive.get(&function).copied().unwrap_or_default();
+            lines.push(format!(
+                "  {:<24} {:>8} {:>14} {:>6.1}% {:>14} {:>6.1}%",
+                function_name(function, symbols),
+                self.call_counts.get(&function).copied().unwrap_or_default(),
+                count,
+                100.0 * count as f64 / total,
+                own,
+                100.0 * own as f64 / total
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+impl Default for CallGraph {
+    fn default() -> CallGraph {
+        CallGraph::new()
+    }
+}
+
+fn root_name(entry_point: u16, symbols: &Symbols) -> String {
+    symbols.label(entry_point).unwrap_or("main").to_string()
+}
+
+fn function_name(address: u16, symbols: &Symbols) -> String {
+    match symbols.label(address) {
+        Some(label) => label.to_string(),
+        None => format!("sub_{:04X}", address),
+    }
+}
+
+fn event(name: &str, depth: usize, start: u64, end: u64) -> String {
+    format!(
+        "{{\"name\":{},\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":1,\"args\":{{\"depth\":{}}}}}",
+        serde_json::to_string(name).unwrap_or_default(),
+        start,
+        end - start,
+        depth
+    )
+}
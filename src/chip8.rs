@@ -1,55 +1,178 @@
-use rand::Rng;
+use std::ops::Range;
 
-use crate::constants::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
-const FONTSET: [u8; 80] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70, 0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0,
-    0x10, 0xF0, 0x10, 0xF0, 0x90, 0x90, 0xF0, 0x10, 0x10, 0xF0, 0x80, 0xF0, 0x10, 0xF0, 0xF0, 0x80,
-    0xF0, 0x90, 0xF0, 0xF0, 0x10, 0x20, 0x40, 0x40, 0xF0, 0x90, 0xF0, 0x90, 0xF0, 0xF0, 0x90, 0xF0,
-    0x10, 0xF0, 0xF0, 0x90, 0xF0, 0x90, 0x90, 0xE0, 0x90, 0xE0, 0x90, 0xE0, 0xF0, 0x80, 0x80, 0x80,
-    0xF0, 0xE0, 0x90, 0x90, 0x90, 0xE0, 0xF0, 0x80, 0xF0, 0x80, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0x80,
-];
+use crate::call_graph::CallGraph;
+use crate::constants::*;
+use crate::coverage::Coverage;
+use crate::fontset::Fontset;
+use crate::memory_access::MemoryAccess;
+use crate::memory_layout::MemoryLayout;
+use crate::profiler::Profiler;
+use crate::quirks::Quirks;
+use crate::trace::Tracer;
+
+/// Snapshot of the CPU registers, for debuggers and other tools.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Registers {
+    pub v: [u8; REGISTERS_V],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u8,
+    pub dt: u8,
+    pub st: u8,
+}
 
 pub struct Chip8State<'a> {
     pub video: &'a [[u8; CHIP8_VIDEO_WIDTH]; CHIP8_VIDEO_HEIGHT],
     pub video_draw: bool,
 }
 
+/// How much of the machine `Chip8::reset` starts over.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Reset {
+    /// Restart the program but keep the RPL user flags, and the random
+    /// seed too if `keep_seed`; otherwise a new seed is picked.
+    Soft { keep_seed: bool },
+    /// Back to the state right after `new` and `load_rom`: user flags
+    /// cleared and the random number generator on the seed from `new`.
+    Hard,
+}
+
 pub struct Chip8 {
     video: [[u8; CHIP8_VIDEO_WIDTH]; CHIP8_VIDEO_HEIGHT], // VRAM
     video_draw: bool,                                     // Redraw frame
-    memory: [u8; CHIP8_MEMORY],                           // RAM
+    memory: Vec<u8>,                                      // RAM
     stack: [u16; STACK_HEIGHT],                           // Stack
     v: [u8; REGISTERS_V],                                 // General purpose registers
     i: u16,                                               // I register (store memory addresses)
-    pc: u16,                     // Program Counter (store currently executing address)
-    sp: u8,                      // Stack Pointer (store topmost level of stack)
-    dt: u8,                      // Delay Timer
-    st: u8,                      // Sound Timer
-    keypad: [bool; KEYPAD_SIZE], // Keypad (16 buttons true or false)
+    pc: u16,                            // Program Counter (store currently executing address)
+    sp: u8,                             // Stack Pointer (store topmost level of stack)
+    dt: u8,                             // Delay Timer
+    st: u8,                             // Sound Timer
+    keypad: [bool; KEYPAD_SIZE],        // Keypad (16 buttons true or false)
+    quirks: Quirks,                     // Interpreter behavior to emulate
+    rng: StdRng,                        // Random number generator for Cxkk
+    vblank_wait: bool,                  // Dxyn is waiting for the next frame
+    layout: MemoryLayout,               // Program, font and memory placement
+    fontset: Fontset,                   // Fonts copied into memory on reset
+    rom: Vec<u8>,                       // Program copied into memory on reset
+    seed: u64,                          // Random seed in use
+    boot_seed: u64,                     // Random seed given to `new`
+    user_flags: Vec<u8>,                // RPL user flags kept outside memory by Fx75/Fx85
+    user_flags_changed: bool,           // Fx75 ran since the flags were last saved
+    poked: bool,                        // Changed from outside since the last reset
+    cycles: u64,                        // Instructions executed since the last reset
+    tracer: Option<Box<Tracer>>,        // Execution trace being written, if any
+    profiler: Option<Box<Profiler>>,    // Instruction counts being collected, if any
+    call_graph: Option<Box<CallGraph>>, // Call stacks being followed, if any
+    coverage: Option<Box<Coverage>>,    // Executed addresses being collected, if any
+    memory_access: Option<Box<MemoryAccess>>, // Per-byte access counts, if any
 }
 
+const STATE_MAGIC: &[u8; 4] = b"C8S1";
+// Save state size, not counting memory
+const STATE_HEADER_SIZE: usize = STATE_MAGIC.len()
+    + CHIP8_VIDEO_WIDTH * CHIP8_VIDEO_HEIGHT
+    + STACK_HEIGHT * 2
+    + REGISTERS_V
+    + 2 // I
+    + 2 // PC
+    + 3; // SP, DT, ST
+
 impl Chip8 {
-    pub fn new() -> Result<Chip8, String> {
+    pub fn new(
+        quirks: Quirks,
+        layout: MemoryLayout,
+        fontset: &Fontset,
+        seed: u64,
+    ) -> Result<Chip8, String> {
+        layout.validate()?;
+
         let mut chip8 = Chip8 {
             video: [[0; CHIP8_VIDEO_WIDTH]; CHIP8_VIDEO_HEIGHT],
             video_draw: false,
-            memory: [0; CHIP8_MEMORY],
+            memory: vec![0; layout.memory_size],
             stack: [0; STACK_HEIGHT],
             v: [0; REGISTERS_V],
             i: 0,
-            pc: 0x200, // Programs start at memory location 0x200
+            pc: layout.entry_point,
             sp: 0,
             dt: 0,
             st: 0,
             keypad: [false; KEYPAD_SIZE],
+            quirks,
+            rng: StdRng::seed_from_u64(seed),
+            vblank_wait: false,
+            layout,
+            fontset: fontset.clone(),
+            rom: Vec::new(),
+            seed,
+            boot_seed: seed,
+            user_flags: vec![0; USER_FLAGS],
+            user_flags_changed: false,
+            poked: false,
+            cycles: 0,
+            tracer: None,
+            profiler: None,
+            call_graph: None,
+            coverage: None,
+            memory_access: None,
         };
+        chip8.power_on();
 
-        for i in 0..FONTSET.len() {
-            chip8.memory[i] = FONTSET[i];
+        Ok(chip8)
+    }
+
+    /// Restart the loaded program, see `Reset` for what is kept.
+    pub fn reset(&mut self, reset: Reset) {
+        match reset {
+            Reset::Soft { keep_seed: true } => {}
+            Reset::Soft { keep_seed: false } => self.seed = rand::random(),
+            Reset::Hard => {
+                self.seed = self.boot_seed;
+                // Only in memory; the flags saved for the ROM are left alone
+                self.user_flags.fill(0);
+                self.user_flags_changed = false;
+            }
         }
+        self.rng = StdRng::seed_from_u64(self.seed);
+        self.power_on();
+    }
 
-        Ok(chip8)
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Clear the machine and copy the fonts and program into memory.
+    fn power_on(&mut self) {
+        self.video = [[0; CHIP8_VIDEO_WIDTH]; CHIP8_VIDEO_HEIGHT];
+        self.video_draw = true;
+        self.memory.fill(0);
+        self.stack = [0; STACK_HEIGHT];
+        self.v = [0; REGISTERS_V];
+        self.i = 0;
+        self.pc = self.layout.entry_point;
+        self.sp = 0;
+        self.dt = 0;
+        self.st = 0;
+        self.keypad = [false; KEYPAD_SIZE];
+        self.vblank_wait = false;
+        self.poked = false;
+        self.cycles = 0;
+        if let Some(call_graph) = &mut self.call_graph {
+            call_graph.restart();
+        }
+        if let Some(memory_access) = &mut self.memory_access {
+            memory_access.reset();
+        }
+
+        let Fontset { small, big } = self.fontset.clone();
+        self.write_memory(self.layout.font_base as usize, &small);
+        self.write_memory(self.layout.big_font_base as usize, &big);
+        let rom = self.rom.clone();
+        self.write_memory(self.layout.load_address as usize, &rom);
     }
 
     pub fn set_key(&mut self, key: u8, pressed: bool) {
@@ -57,18 +180,219 @@ impl Chip8 {
     }
 
     pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
-        if rom.len() > CHIP8_MEMORY - 0x200 {
-            return Err("ROM too large".to_string());
-        }
-        for (i, &byte) in rom.iter().enumerate() {
-            self.memory[0x200 + i] = byte;
+        if rom.len() > self.layout.program_capacity() {
+            return Err(format!(
+                "ROM is {} bytes, but only {} fit after {:#05x}",
+                rom.len(),
+                self.layout.program_capacity(),
+                self.layout.load_address
+            ));
         }
+        self.write_memory(self.layout.load_address as usize, rom);
+        self.rom = rom.to_vec();
         Ok(())
     }
 
-    pub fn cycle(&mut self) -> Chip8State {
+    /// Replace the RPL user flags, e.g. with ones saved by an earlier run.
+    /// Their length is how many registers Fx75/Fx85 can save and restore.
+    pub fn set_user_flags(&mut self, flags: Vec<u8>) {
+        self.user_flags = flags;
+        self.user_flags_changed = false;
+    }
+
+    pub fn user_flags(&self) -> &[u8] {
+        &self.user_flags
+    }
+
+    /// Whether Fx75 stored new flags since the last call.
+    pub fn take_user_flags_changed(&mut self) -> bool {
+        std::mem::take(&mut self.user_flags_changed)
+    }
+
+    /// Addresses the loaded ROM occupies, so tools know where the program ends.
+    pub fn program_range(&self) -> Range<usize> {
+        let start = self.layout.load_address as usize;
+        start..start + self.rom.len()
+    }
+
+    /// The program as loaded, before it changed anything in memory.
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            v: self.v,
+            i: self.i,
+            pc: self.pc,
+            sp: self.sp,
+            dt: self.dt,
+            st: self.st,
+        }
+    }
+
+    /// Return addresses of the active subroutine calls, outermost first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[1..=(self.sp as usize).min(STACK_HEIGHT - 1)]
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Byte at an address, which wraps around at the end of memory.
+    pub fn read_byte(&self, address: usize) -> u8 {
+        self.memory[self.address(address)]
+    }
+
+    pub fn video(&self) -> &[[u8; CHIP8_VIDEO_WIDTH]; CHIP8_VIDEO_HEIGHT] {
+        &self.video
+    }
+
+    pub fn keypad(&self) -> &[bool; KEYPAD_SIZE] {
+        &self.keypad
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn layout(&self) -> MemoryLayout {
+        self.layout
+    }
+
+    // The setters below change the machine behind the program's back, so a
+    // run that uses them can no longer be reproduced from its seed and input
+    // alone. `poked` tells tools such as recorders that this happened.
+
+    /// Write a byte to memory, wrapping around at the end. Affects determinism.
+    pub fn write_byte(&mut self, address: usize, value: u8) {
+        self.write_memory(address, &[value]);
+        self.poked = true;
+    }
+
+    /// Set register V0..VF. Affects determinism.
+    ///
+    /// Panics if `register` is 16 or more.
+    pub fn set_register(&mut self, register: usize, value: u8) {
+        self.v[register] = value;
+        self.poked = true;
+    }
+
+    /// Set the I register. Affects determinism.
+    pub fn set_i(&mut self, value: u16) {
+        self.i = value;
+        self.poked = true;
+    }
+
+    /// Continue execution at another address; stepping past 0xFFFF wraps to 0. Affects determinism.
+    pub fn set_pc(&mut self, address: u16) {
+        self.pc = address;
+        self.poked = true;
+    }
+
+    /// Set the delay and sound timers. Affects determinism.
+    pub fn set_timers(&mut self, delay: u8, sound: u8) {
+        self.dt = delay;
+        self.st = sound;
+        self.poked = true;
+    }
+
+    /// Instructions executed since the last reset.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Start or stop writing an execution trace.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer.map(Box::new);
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take().map(|tracer| *tracer)
+    }
+
+    /// Start or stop counting where instructions are spent.
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler.map(Box::new);
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take().map(|profiler| *profiler)
+    }
+
+    /// Start or stop following subroutine calls. The program is taken to be
+    /// outside any subroutine when this is called.
+    pub fn set_call_graph(&mut self, call_graph: Option<CallGraph>) {
+        self.call_graph = call_graph.map(|mut call_graph| {
+            call_graph.restart();
+            Box::new(call_graph)
+        });
+    }
+
+    pub fn take_call_graph(&mut self) -> Option<CallGraph> {
+        self.call_graph.take().map(|call_graph| *call_graph)
+    }
+
+    /// Start or stop recording which instructions run and which way skips go.
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+        self.coverage = coverage.map(Box::new);
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take().map(|coverage| *coverage)
+    }
+
+    /// Start or stop counting reads, writes and executions per byte. Counts
+    /// moved over from another machine are kept, but not which stores wrote
+    /// its memory.
+    pub fn set_memory_access(&mut self, memory_access: Option<MemoryAccess>) {
+        self.memory_access = memory_access.map(Box::new);
+        if let Some(memory_access) = &mut self.memory_access {
+            memory_access.reset();
+        }
+    }
+
+    pub fn take_memory_access(&mut self) -> Option<MemoryAccess> {
+        self.memory_access
+            .take()
+            .map(|memory_access| *memory_access)
+    }
+
+    /// Whether any of the setters above were used since the last reset.
+    pub fn poked(&self) -> bool {
+        self.poked
+    }
+
+    /// Copy bytes into memory, wrapping around at the end.
+    fn write_memory(&mut self, address: usize, bytes: &[u8]) {
+        for (offset, &byte) in bytes.iter().enumerate() {
+            let index = self.address(address + offset);
+            self.memory[index] = byte;
+            if let Some(tracer) = &mut self.tracer {
+                tracer.note_write(index, byte);
+            }
+        }
+    }
+
+    /// Memory index for an address, which wraps around at the end of memory.
+    fn address(&self, address: usize) -> usize {
+        address % self.memory.len()
+    }
+
+    pub fn cycle(&mut self) -> Chip8State<'_> {
         let opcode = self.gen_opcode();
-        self.run_opcode(opcode);
+        if self.tracer.is_some()
+            || self.profiler.is_some()
+            || self.call_graph.is_some()
+            || self.coverage.is_some()
+            || self.memory_access.is_some()
+        {
+            self.run_instrumented(opcode);
+        } else {
+            self.run_opcode(opcode);
+        }
+        self.cycles += 1;
 
         let should_draw = self.video_draw;
         self.video_draw = false;
@@ -79,7 +403,77 @@ impl Chip8 {
         }
     }
 
-    pub fn update_timers(&mut self) {
+    /// Run one 60 Hz frame: `instructions` cycles followed by a single timer tick.
+    pub fn run_frame(&mut self, instructions: u32) -> Chip8State<'_> {
+        let mut should_draw = false;
+        self.vblank_wait = false;
+        for _ in 0..instructions {
+            should_draw |= self.cycle().video_draw;
+            if self.vblank_wait {
+                break; // Display wait quirk: the rest of the frame is spent waiting
+            }
+        }
+        self.update_timers();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.end_frame();
+        }
+
+        Chip8State {
+            video: &self.video,
+            video_draw: should_draw,
+        }
+    }
+
+    /// Snapshot of the machine, excluding the keypad and random number generator.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(STATE_HEADER_SIZE + self.memory.len());
+        state.extend_from_slice(STATE_MAGIC);
+        state.extend(self.video.iter().flatten());
+        state.extend_from_slice(&self.memory);
+        state.extend(self.stack.iter().flat_map(|entry| entry.to_le_bytes()));
+        state.extend_from_slice(&self.v);
+        state.extend_from_slice(&self.i.to_le_bytes());
+        state.extend_from_slice(&self.pc.to_le_bytes());
+        state.extend_from_slice(&[self.sp, self.dt, self.st]);
+        state
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        if !state.starts_with(STATE_MAGIC) {
+            return Err("Not a CHIP-8 save state".to_string());
+        }
+        if state.len() != STATE_HEADER_SIZE + self.memory.len() {
+            return Err("Save state is for a different memory size".to_string());
+        }
+
+        let mut rest = &state[STATE_MAGIC.len()..];
+        let mut take = |count: usize| {
+            let (bytes, remaining) = rest.split_at(count);
+            rest = remaining;
+            bytes
+        };
+
+        for row in self.video.iter_mut() {
+            row.copy_from_slice(take(CHIP8_VIDEO_WIDTH));
+        }
+        let memory_size = self.memory.len();
+        self.memory.copy_from_slice(take(memory_size));
+        for entry in self.stack.iter_mut() {
+            *entry = u16::from_le_bytes([take(1)[0], take(1)[0]]);
+        }
+        self.v.copy_from_slice(take(REGISTERS_V));
+        self.i = u16::from_le_bytes([take(1)[0], take(1)[0]]);
+        self.pc = u16::from_le_bytes([take(1)[0], take(1)[0]]);
+        let [sp, dt, st] = [take(1)[0], take(1)[0], take(1)[0]];
+        self.sp = sp;
+        self.dt = dt;
+        self.st = st;
+
+        self.video_draw = true;
+        Ok(())
+    }
+
+    fn update_timers(&mut self) {
         if self.dt > 0 {
             self.dt -= 1;
         }
@@ -88,14 +482,40 @@ impl Chip8 {
         }
     }
 
-    fn gen_opcode(&mut self) -> u16 {
-        if self.pc as usize + 1 >= CHIP8_MEMORY {
-            return 0; // Prevent overflow
+    /// Run an opcode, writing it to the trace if the filter lets it through
+    /// and counting it in the profile, call graph, coverage and memory access.
+    fn run_instrumented(&mut self, opcode: u16) {
+        let registers = self.registers();
+        let cycle = self.cycles;
+        let traced = self
+            .tracer
+            .as_mut()
+            .is_some_and(|tracer| tracer.begin(cycle, registers.pc));
+
+        self.run_opcode(opcode);
+
+        if traced && let Some(tracer) = &mut self.tracer {
+            tracer.record(cycle, opcode, registers);
         }
-        let high_byte = self.memory[self.pc as usize];
-        let low_byte = self.memory[(self.pc + 1) as usize];
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(registers.pc, opcode, self.pc);
+        }
+        if let Some(call_graph) = &mut self.call_graph {
+            call_graph.record(opcode, self.pc);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(registers.pc, opcode, self.pc);
+        }
+        if let Some(memory_access) = &mut self.memory_access {
+            memory_access.record(registers.pc, opcode, registers.i);
+        }
+    }
+
+    fn gen_opcode(&mut self) -> u16 {
+        let high_byte = self.memory[self.address(self.pc as usize)];
+        let low_byte = self.memory[self.address(self.pc as usize + 1)];
         let opcode = ((high_byte as usize) << 8) | (low_byte as usize);
-        return opcode as u16;
+        opcode as u16
     }
 
     fn run_opcode(&mut self, opcode: u16) {
@@ -139,17 +559,20 @@ impl Chip8 {
             (0x0f, _, 0x01, 0x08) => self.op_fx18(opcode),
             (0x0f, _, 0x01, 0x0e) => self.op_fx1e(opcode),
             (0x0f, _, 0x02, 0x09) => self.op_fx29(opcode),
+            (0x0f, _, 0x03, 0x00) => self.op_fx30(opcode),
             (0x0f, _, 0x03, 0x03) => self.op_fx33(opcode),
             (0x0f, _, 0x05, 0x05) => self.op_fx55(opcode),
             (0x0f, _, 0x06, 0x05) => self.op_fx65(opcode),
-            _ => self.pc += 2,
+            (0x0f, _, 0x07, 0x05) => self.op_fx75(opcode),
+            (0x0f, _, 0x08, 0x05) => self.op_fx85(opcode),
+            _ => self.pc = self.pc.wrapping_add(2),
         };
     }
 
     /// 0nnn - SYS addr
     /// Jump to a machine code routine at nnn (ignored by modern interpreters).
     fn op_0nnn(&mut self) {
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 00E0 - CLS
@@ -161,7 +584,7 @@ impl Chip8 {
             }
         }
         self.video_draw = true;
-        self.pc += 2; // Next instrction
+        self.pc = self.pc.wrapping_add(2); // Next instrction
     }
 
     /// 00EE - RET
@@ -194,9 +617,9 @@ impl Chip8 {
         let kk: u8 = (opcode & 0x00FF) as u8;
 
         if self.v[x] == kk {
-            self.pc += 4; // Skip
+            self.pc = self.pc.wrapping_add(4); // Skip
         } else {
-            self.pc += 2; // Next
+            self.pc = self.pc.wrapping_add(2); // Next
         }
     }
 
@@ -207,9 +630,9 @@ impl Chip8 {
         let kk: u8 = (opcode & 0x00FF) as u8;
 
         if self.v[x] != kk {
-            self.pc += 4;
+            self.pc = self.pc.wrapping_add(4);
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
@@ -220,9 +643,9 @@ impl Chip8 {
         let y: usize = ((opcode & 0x00F0) >> 4) as usize;
 
         if self.v[x] == self.v[y] {
-            self.pc += 4;
+            self.pc = self.pc.wrapping_add(4);
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
@@ -230,7 +653,7 @@ impl Chip8 {
     /// Set Vx = kk.
     fn op_6xkk(&mut self, opcode: u16) {
         self.v[((opcode & 0x0F00) >> 8) as usize] = (opcode & 0x00FF) as u8;
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 7xkk - ADD Vx, byte
@@ -239,7 +662,7 @@ impl Chip8 {
         let x = ((opcode & 0x0F00) >> 8) as usize;
         let kk = (opcode & 0x00FF) as u8;
         self.v[x] = self.v[x].wrapping_add(kk);
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy0 - LD Vx, Vy
@@ -249,7 +672,7 @@ impl Chip8 {
         let y: usize = ((opcode & 0x00F0) >> 4) as usize;
 
         self.v[x] = self.v[y];
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy1 - OR Vx, Vy
@@ -262,8 +685,10 @@ impl Chip8 {
         let vy = self.v[y];
 
         self.v[x] = vx | vy;
-        self.v[0xF] = 0;
-        self.pc += 2;
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy2 - AND Vx, Vy
@@ -276,8 +701,10 @@ impl Chip8 {
         let vy = self.v[y];
 
         self.v[x] = vx & vy;
-        self.v[0xF] = 0;
-        self.pc += 2;
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy3 - XOR Vx, Vy
@@ -290,8 +717,10 @@ impl Chip8 {
         let vy = self.v[y];
 
         self.v[x] = vx ^ vy;
-        self.v[0xF] = 0;
-        self.pc += 2;
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy4 - ADD Vx, Vy
@@ -308,7 +737,7 @@ impl Chip8 {
         self.v[x] = sum as u8;
         self.v[0xF] = if sum > 255 { 1 } else { 0 };
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy5 - SUB Vx, Vy
@@ -323,7 +752,7 @@ impl Chip8 {
         self.v[x] = vx.wrapping_sub(vy);
         self.v[0xF] = if vx >= vy { 1 } else { 0 };
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy6 - SHR Vx {, Vy}
@@ -332,13 +761,16 @@ impl Chip8 {
         let x: usize = ((opcode & 0x0F00) >> 8) as usize;
         let y: usize = ((opcode & 0x00F0) >> 4) as usize;
 
-        let vx = self.v[x];
-        let vy = self.v[y];
-        
-        self.v[x] = vy;
-        self.v[x] = self.v[x] >> 1;
-        self.v[0xF] = vx & 0x1;
-        self.pc += 2;
+        // Shifting quirk: shift Vx in place instead of copying Vy
+        let value = if self.quirks.shifting {
+            self.v[x]
+        } else {
+            self.v[y]
+        };
+
+        self.v[x] = value >> 1;
+        self.v[0xF] = value & 0x1;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy7 - SUBN Vx, Vy
@@ -353,7 +785,7 @@ impl Chip8 {
         self.v[x] = vy.wrapping_sub(vx);
         self.v[0xF] = if vy >= vx { 1 } else { 0 };
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xyE - SHL Vx {, Vy}
@@ -362,13 +794,15 @@ impl Chip8 {
         let x: usize = ((opcode & 0x0F00) >> 8) as usize;
         let y: usize = ((opcode & 0x00F0) >> 4) as usize;
 
-        let vx = self.v[x];
-        let vy = self.v[y];
+        let value = if self.quirks.shifting {
+            self.v[x]
+        } else {
+            self.v[y]
+        };
 
-        self.v[x] = vy;
-        self.v[x] = self.v[x] << 1;
-        self.v[0xF] = (vx & 0x80) >> 7;
-        self.pc += 2;
+        self.v[x] = value << 1;
+        self.v[0xF] = (value & 0x80) >> 7;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 9xy0 - SNE Vx, Vy
@@ -378,9 +812,9 @@ impl Chip8 {
         let y: usize = ((opcode & 0x00F0) >> 4) as usize;
 
         if self.v[x] != self.v[y] {
-            self.pc += 4;
+            self.pc = self.pc.wrapping_add(4);
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
@@ -388,13 +822,19 @@ impl Chip8 {
     /// Set I = nnn.
     fn op_annn(&mut self, opcode: u16) {
         self.i = opcode & 0x0FFF;
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Bnnn - JP V0, addr
     /// Jump to location nnn + V0.
     fn op_bnnn(&mut self, opcode: u16) {
-        self.pc = (opcode & 0x0FFF) + self.v[0] as u16;
+        // Jumping quirk: SUPER-CHIP reads the offset from Vx, x being the top nibble of nnn
+        let x: usize = if self.quirks.jumping {
+            ((opcode & 0x0F00) >> 8) as usize
+        } else {
+            0
+        };
+        self.pc = (opcode & 0x0FFF) + self.v[x] as u16;
     }
 
     /// Cxkk - RND Vx, byte
@@ -403,11 +843,10 @@ impl Chip8 {
         let x: usize = ((opcode & 0x0F00) >> 8) as usize;
         let kk: u8 = (opcode & 0x00FF) as u8;
 
-        let mut rng = rand::rng();
-        let random_byte: u8 = rng.random::<u8>();
+        let random_byte: u8 = self.rng.random::<u8>();
 
         self.v[x] = random_byte & kk;
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Dxyn - DRW Vx, Vy, nibble
@@ -423,21 +862,27 @@ impl Chip8 {
         self.v[0xF] = 0;
 
         for y_offset in 0..n {
-            let current_y = start_y + y_offset;
+            let mut current_y = start_y + y_offset;
 
-            // Clip at bottom edge
+            // Clip at bottom edge, or wrap around without the clipping quirk
             if current_y >= CHIP8_VIDEO_HEIGHT {
-                break;
+                if self.quirks.clipping {
+                    break;
+                }
+                current_y %= CHIP8_VIDEO_HEIGHT;
             }
 
-            let sprite_byte = self.memory[(self.i as usize) + y_offset];
+            let sprite_byte = self.memory[self.address(self.i as usize + y_offset)];
 
             for x_offset in 0..8 {
-                let current_x = start_x + x_offset;
+                let mut current_x = start_x + x_offset;
 
                 // Clip at right edge
                 if current_x >= CHIP8_VIDEO_WIDTH {
-                    break;
+                    if self.quirks.clipping {
+                        break;
+                    }
+                    current_x %= CHIP8_VIDEO_WIDTH;
                 }
 
                 if (sprite_byte & (0x80 >> x_offset)) != 0 {
@@ -449,7 +894,8 @@ impl Chip8 {
             }
         }
         self.video_draw = true;
-        self.pc += 2;
+        self.vblank_wait = self.quirks.display_wait;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Ex9E - SKP Vx
@@ -459,9 +905,9 @@ impl Chip8 {
         let key = self.v[x] as usize;
 
         if self.keypad[key] {
-            self.pc += 4;
+            self.pc = self.pc.wrapping_add(4);
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
@@ -472,9 +918,9 @@ impl Chip8 {
         let key = self.v[x] as usize;
 
         if !self.keypad[key] {
-            self.pc += 4;
+            self.pc = self.pc.wrapping_add(4);
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
@@ -483,7 +929,7 @@ impl Chip8 {
     fn op_fx07(&mut self, opcode: u16) {
         let x: usize = ((opcode & 0x0F00) >> 8) as usize;
         self.v[x] = self.dt;
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Fx0A - LD Vx, K
@@ -494,7 +940,7 @@ impl Chip8 {
         for (key, &pressed) in self.keypad.iter().enumerate() {
             if pressed {
                 self.v[x] = key as u8;
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
                 return;
             }
         }
@@ -505,7 +951,7 @@ impl Chip8 {
     fn op_fx15(&mut self, opcode: u16) {
         let x: usize = ((opcode & 0x0F00) >> 8) as usize;
         self.dt = self.v[x];
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Fx18 - LD ST, Vx
@@ -513,7 +959,7 @@ impl Chip8 {
     fn op_fx18(&mut self, opcode: u16) {
         let x: usize = ((opcode & 0x0F00) >> 8) as usize;
         self.st = self.v[x];
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Fx1E - ADD I, Vx
@@ -521,16 +967,25 @@ impl Chip8 {
     fn op_fx1e(&mut self, opcode: u16) {
         let x: usize = ((opcode & 0x0F00) >> 8) as usize;
         self.i = self.i.wrapping_add(self.v[x] as u16);
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Fx29 - LD F, Vx
     /// Set I = location of sprite for digit Vx.
     fn op_fx29(&mut self, opcode: u16) {
         let x: usize = ((opcode & 0x0F00) >> 8) as usize;
-        let digit = self.v[x] as u16;
-        self.i = digit * 5; // Sprite 5 bytes
-        self.pc += 2;
+        let digit = (self.v[x] & 0x0F) as u16;
+        self.i = self.layout.font_base + digit * 5; // Sprite 5 bytes
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    /// Fx30 - LD HF, Vx
+    /// Set I = location of big 8x10 sprite for digit Vx.
+    fn op_fx30(&mut self, opcode: u16) {
+        let x: usize = ((opcode & 0x0F00) >> 8) as usize;
+        let digit = (self.v[x] & 0x0F) as u16;
+        self.i = self.layout.big_font_base + digit * 10; // Sprite 10 bytes
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Fx33 - LD B, Vx
@@ -539,11 +994,10 @@ impl Chip8 {
         let x: usize = ((opcode & 0x0F00) >> 8) as usize;
         let value = self.v[x];
 
-        self.memory[self.i as usize] = value / 100;
-        self.memory[(self.i + 1) as usize] = (value % 100) / 10;
-        self.memory[(self.i + 2) as usize] = value % 10;
+        let digits = [value / 100, (value % 100) / 10, value % 10];
+        self.write_memory(self.i as usize, &digits);
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Fx55 - LD [I], Vx
@@ -551,13 +1005,14 @@ impl Chip8 {
     fn op_fx55(&mut self, opcode: u16) {
         let x: usize = ((opcode & 0x0F00) >> 8) as usize;
 
-        for register_index in 0..=x {
-            self.memory[(self.i as usize) + register_index] = self.v[register_index];
-        }
+        let registers = self.v;
+        self.write_memory(self.i as usize, &registers[..=x]);
 
-        self.i += (x as u16) + 1;
+        if self.quirks.memory {
+            self.i = self.i.wrapping_add(x as u16 + 1);
+        }
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Fx65 - LD Vx, [I]
@@ -566,11 +1021,38 @@ impl Chip8 {
         let x: usize = ((opcode & 0x0F00) >> 8) as usize;
 
         for register_index in 0..=x {
-            self.v[register_index] = self.memory[(self.i as usize) + register_index];
+            self.v[register_index] = self.memory[self.address(self.i as usize + register_index)];
+        }
+
+        if self.quirks.memory {
+            self.i = self.i.wrapping_add(x as u16 + 1);
         }
 
-        self.i += (x as u16) + 1;
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    /// Fx75 - LD R, Vx
+    /// Store registers V0 through Vx in the RPL user flags.
+    fn op_fx75(&mut self, opcode: u16) {
+        let x: usize = ((opcode & 0x0F00) >> 8) as usize;
+        let count = (x + 1).min(self.user_flags.len()); // Registers beyond the flags are dropped
+
+        if self.user_flags[..count] != self.v[..count] {
+            self.user_flags[..count].copy_from_slice(&self.v[..count]);
+            self.user_flags_changed = true;
+        }
+
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    /// Fx85 - LD Vx, R
+    /// Read registers V0 through Vx from the RPL user flags.
+    fn op_fx85(&mut self, opcode: u16) {
+        let x: usize = ((opcode & 0x0F00) >> 8) as usize;
+        let count = (x + 1).min(self.user_flags.len());
+
+        self.v[..count].copy_from_slice(&self.user_flags[..count]);
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 }
@@ -0,0 +1,407 @@
+use crate::VIDEO_SCALE;
+use crate::display_filter::DisplayFilter;
+use crate::fontset::Fontset;
+use crate::memory_layout::MemoryLayout;
+use crate::palette::Palette;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::symbols::Symbols;
+use crate::trace::{TraceFilter, TraceFormat};
+use crate::trace_diff::Comparison;
+
+pub const USAGE: &str = "\
+Usage: chip8-emulator [OPTIONS] <ROM>
+       chip8-emulator rom-info [--database <FILE>] <ROM>
+       chip8-emulator trace-diff [--ignore <FIELDS>] [--context <N>] [--symbols <FILE>]
+                                 <TRACE> <TRACE>
+       chip8-emulator coverage-report [--symbols <FILE>] <ROM> <COVERAGE>...
+
+Commands:
+  rom-info             Print a ROM's hash, database entry and detected platform
+  trace-diff           Show where two --trace files first differ; exits with 1
+                       if they do. --ignore leaves out fields (cycle, pc, opcode,
+                       v, i, sp, timers, writes), --context sets how many
+                       entries are shown around the difference (default 5)
+  coverage-report      Disassemble a ROM with the counts from --coverage files,
+                       marking code that never ran (!) and skips that only
+                       went one way (?)
+
+Options:
+  --speed <N>          Instructions per frame (default: from the ROM database, else 8)
+  --scale <N>          Initial window scale (default 20)
+  --platform <NAME>    Target platform: chip8, schip, xochip
+                       (default: from the ROM database, else chip8)
+  --quirks <PROFILE>   Quirk profile with optional toggles, e.g. schip,-clipping
+                       (quirks: vf_reset, memory, shifting, jumping, clipping,
+                       display_wait; default: from the ROM database, else the
+                       platform's profile)
+  --layout <LAYOUT>    Memory layout: chip8, eti660 (programs at 0x600) or xochip,
+                       with optional overrides, e.g. chip8,entry=0x300,load=0x300
+                       (entry, load, font, bigfont, memory; default: the platform's)
+  --font <FONT>        Hex digit font: vip, eti660, dream6800, fishnchips, schip,
+                       octo, or a file of 80 bytes plus an optional 100 or 160
+                       byte big font (default: the platform's)
+  --big-font <FONT>    8x10 digit font for Fx30: schip, octo, or a 100 or 160
+                       byte file
+  --palette <THEME>    Color theme, or 2, 4 or 16 comma separated hex colors
+  --database <FILE>    chip-8-database programs.json to use (default: programs.json
+                       in the user data directory, else the bundled one)
+  --no-database        Don't look the ROM up in the database
+  --filter <MODE>      Anti-flicker filter: off, fade or blend, e.g. fade:0.8
+  --seed <N>           Seed for the random number generator
+  --keymap <FILE>      Keyboard mapping file with `key = digit` lines
+  --flags-dir <DIR>    Where SUPER-CHIP user flags (Fx75/Fx85) are saved per ROM
+                       (default: the user data directory)
+  --state <FILE>       Save state to load on start; F5/F9 save/load it
+                       (default <ROM>.state, not loaded on start)
+  --watch              Reload and reset when the ROM file changes
+  --keep-seed          With --watch, reuse the random seed after a reload
+  --replay-input       With --watch, replay the keys pressed since the last
+                       reset after a reload
+  --trace <FILE>       Write every executed instruction to FILE
+  --trace-format <FMT> text (default) or binary
+  --trace-filter <SPEC>
+                       Only trace some instructions, e.g.
+                       pc=0x200-0x2ff,cycles=1000-2000,after=0x2a4; addresses
+                       can be --symbols labels, e.g. after=draw_player+4
+  --profile            Print where instructions were spent when the run ends
+  --call-graph <FILE>  Follow subroutine calls, print instructions spent per
+                       subroutine and write the stacks in folded format for
+                       flame graph tools
+  --chrome-trace <FILE>
+                       Write subroutine calls as a Chrome trace-event timeline
+  --coverage <FILE>    Record which instructions ran and which way skips went,
+                       adding to the counts already in FILE
+  --heatmap <FILE>     Count reads, writes and executions per byte of memory,
+                       report memory use and self-modifying code, and write a
+                       64x64 heatmap of memory to FILE as a BMP image
+  --symbols <FILE>     Labels for addresses, as `0x2a0 label` lines, shown as
+                       `label+4` in traces, reports and disassembly
+  --headless           Run without a window and print the final screen
+  --frames <N>         Exit after N frames (needed for reports when headless)
+  --integer-scale      Only scale the picture by whole multiples
+  --vsync              Sync presenting to the display refresh
+  --paused             Start paused
+  -h, --help           Print this help";
+
+/// Settings chosen on the command line.
+pub struct Options {
+    pub rom: String,
+    pub speed: Option<u32>, // Options left unset come from the ROM database
+    pub scale: u32,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub layout: Option<MemoryLayout>,
+    pub font: Option<Fontset>,
+    pub big_font: Option<Vec<u8>>,
+    pub palette: Option<Palette>,
+    pub database: Option<String>,
+    pub no_database: bool,
+    pub filter: DisplayFilter,
+    pub seed: Option<u64>,
+    pub keymap: Option<String>,
+    pub flags_dir: Option<String>,
+    pub state: Option<String>,
+    pub watch: bool,
+    pub keep_seed: bool,    // Reloads reuse the seed instead of picking a new one
+    pub replay_input: bool, // Reloads replay the input since the last reset
+    pub trace: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+    pub profile: bool,
+    pub call_graph: Option<String>,
+    pub chrome_trace: Option<String>,
+    pub coverage: Option<String>,
+    pub heatmap: Option<String>,
+    pub symbols: Symbols,
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub integer_scale: bool,
+    pub vsync: bool,
+    pub paused: bool,
+}
+
+pub enum Command {
+    Run(Box<Options>),
+    RomInfo {
+        rom: String,
+        database: Option<String>,
+    },
+    TraceDiff {
+        traces: (String, String),
+        comparison: Comparison,
+        context: usize,
+        symbols: Symbols,
+    },
+    CoverageReport {
+        rom: String,
+        coverage: Vec<String>,
+        symbols: Symbols,
+    },
+    Help,
+}
+
+impl Options {
+    /// Parse the arguments following the program name.
+    pub fn parse(args: &[String]) -> Result<Command, String> {
+        if args.first().is_some_and(|arg| arg == "rom-info") {
+            return parse_rom_info(&args[1..]);
+        }
+        if args.first().is_some_and(|arg| arg == "trace-diff") {
+            return parse_trace_diff(&args[1..]);
+        }
+        if args.first().is_some_and(|arg| arg == "coverage-report") {
+            return parse_coverage_report(&args[1..]);
+        }
+
+        let mut rom = None;
+        let mut speed = None;
+        let mut scale = VIDEO_SCALE as u32;
+        let mut platform = None;
+        let mut quirks = None;
+        let mut layout = None;
+        let mut font = None;
+        let mut big_font = None;
+        let mut palette = None;
+        let mut database = None;
+        let mut no_database = false;
+        let mut filter = DisplayFilter::default();
+        let mut seed = None;
+        let mut keymap = None;
+        let mut flags_dir = None;
+        let mut state = None;
+        let mut watch = false;
+        let mut keep_seed = false;
+        let mut replay_input = false;
+        let mut trace = None;
+        let mut trace_format = TraceFormat::default();
+        let mut trace_filter = None;
+        let mut profile = false;
+        let mut call_graph = None;
+        let mut chrome_trace = None;
+        let mut coverage = None;
+        let mut heatmap = None;
+        let mut symbols = None;
+        let mut headless = false;
+        let mut frames = None;
+        let mut integer_scale = false;
+        let mut vsync = false;
+        let mut paused = false;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .map(String::as_str)
+                    .ok_or(format!("{} needs a value", arg))
+            };
+
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Command::Help),
+                "--speed" => speed = Some(parse_number(arg, value()?)?),
+                "--scale" => scale = parse_number(arg, value()?)?,
+                "--platform" => platform = Some(Platform::parse(value()?)?),
+                "--quirks" => quirks = Some(Quirks::parse(value()?)?),
+                "--layout" => layout = Some(MemoryLayout::parse(value()?)?),
+                "--font" => font = Some(Fontset::parse(value()?)?),
+                "--big-font" => big_font = Some(Fontset::parse_big_font(value()?)?),
+                "--palette" => palette = Some(Palette::parse(value()?)?),
+                "--database" => database = Some(value()?.to_string()),
+                "--no-database" => no_database = true,
+                "--filter" => filter = DisplayFilter::parse(value()?)?,
+                "--seed" => seed = Some(parse_number(arg, value()?)?),
+                "--keymap" => keymap = Some(value()?.to_string()),
+                "--flags-dir" => flags_dir = Some(value()?.to_string()),
+                "--state" => state = Some(value()?.to_string()),
+                "--watch" => watch = true,
+                "--keep-seed" => keep_seed = true,
+                "--replay-input" => replay_input = true,
+                "--trace" => trace = Some(value()?.to_string()),
+                "--trace-format" => trace_format = TraceFormat::parse(value()?)?,
+                "--trace-filter" => trace_filter = Some(value()?),
+                "--profile" => profile = true,
+                "--call-graph" => call_graph = Some(value()?.to_string()),
+                "--chrome-trace" => chrome_trace = Some(value()?.to_string()),
+                "--coverage" => coverage = Some(value()?.to_string()),
+                "--heatmap" => heatmap = Some(value()?.to_string()),
+                "--symbols" => symbols = Some(value()?.to_string()),
+                "--headless" => headless = true,
+                "--frames" => frames = Some(parse_number(arg, value()?)?),
+                "--integer-scale" => integer_scale = true,
+                "--vsync" => vsync = true,
+                "--paused" => paused = true,
+                option if option.starts_with('-') => {
+                    return Err(format!("Unknown option {}", option));
+                }
+                path if rom.is_none() => rom = Some(path.to_string()),
+                path => return Err(format!("Unexpected argument {}", path)),
+            }
+        }
+
+        let rom = rom.ok_or("No ROM given")?;
+        if speed == Some(0) || scale == 0 {
+            return Err("--speed and --scale must be at least 1".to_string());
+        }
+        // A headless run only ends, and writes its reports, after --frames
+        let writes_output = trace.is_some()
+            || profile
+            || call_graph.is_some()
+            || chrome_trace.is_some()
+            || coverage.is_some()
+            || heatmap.is_some();
+        if headless && frames.is_none() && writes_output {
+            return Err(
+                "--headless needs --frames with --trace, --profile, --call-graph, \
+                 --chrome-trace, --coverage or --heatmap"
+                    .to_string(),
+            );
+        }
+        // Addresses in the trace filter can be labels
+        let symbols = load_symbols(symbols)?;
+        let trace_filter = match trace_filter {
+            Some(spec) => TraceFilter::parse(spec, &symbols)?,
+            None => TraceFilter::default(),
+        };
+
+        Ok(Command::Run(Box::new(Options {
+            rom,
+            state,
+            speed,
+            scale,
+            platform,
+            quirks,
+            layout,
+            font,
+            big_font,
+            palette,
+            database,
+            no_database,
+            filter,
+            seed,
+            keymap,
+            flags_dir,
+            watch,
+            keep_seed,
+            replay_input,
+            trace,
+            trace_format,
+            trace_filter,
+            profile,
+            call_graph,
+            chrome_trace,
+            coverage,
+            heatmap,
+            symbols,
+            headless,
+            frames,
+            integer_scale,
+            vsync,
+            paused,
+        })))
+    }
+}
+
+fn parse_rom_info(args: &[String]) -> Result<Command, String> {
+    let mut rom = None;
+    let mut database = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--database" => {
+                database = Some(args.next().ok_or("--database needs a value")?.to_string())
+            }
+            option if option.starts_with('-') => {
+                return Err(format!("Unknown rom-info option {}", option));
+            }
+            path if rom.is_none() => rom = Some(path.to_string()),
+            path => return Err(format!("Unexpected argument {}", path)),
+        }
+    }
+
+    Ok(Command::RomInfo {
+        rom: rom.ok_or("No ROM given")?,
+        database,
+    })
+}
+
+fn parse_trace_diff(args: &[String]) -> Result<Command, String> {
+    let mut traces = Vec::new();
+    let mut comparison = Comparison::all();
+    let mut context = 5;
+    let mut symbols = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .map(String::as_str)
+                .ok_or(format!("{} needs a value", arg))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--ignore" => comparison = Comparison::ignoring(value()?)?,
+            "--context" => context = parse_number(arg, value()?)?,
+            "--symbols" => symbols = Some(value()?.to_string()),
+            option if option.starts_with('-') => {
+                return Err(format!("Unknown trace-diff option {}", option));
+            }
+            path if traces.len() < 2 => traces.push(path.to_string()),
+            path => return Err(format!("Unexpected argument {}", path)),
+        }
+    }
+
+    let [first, second]: [String; 2] = traces
+        .try_into()
+        .map_err(|_| "trace-diff needs two trace files")?;
+    Ok(Command::TraceDiff {
+        traces: (first, second),
+        comparison,
+        context,
+        symbols: load_symbols(symbols)?,
+    })
+}
+
+fn parse_coverage_report(args: &[String]) -> Result<Command, String> {
+    let mut paths = Vec::new();
+    let mut symbols = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--symbols" => {
+                symbols = Some(args.next().ok_or("--symbols needs a value")?.to_string())
+            }
+            option if option.starts_with('-') => {
+                return Err(format!("Unknown coverage-report option {}", option));
+            }
+            path => paths.push(path.to_string()),
+        }
+    }
+
+    if paths.len() < 2 {
+        return Err("coverage-report needs a ROM and at least one coverage file".to_string());
+    }
+    let rom = paths.remove(0);
+    Ok(Command::CoverageReport {
+        rom,
+        coverage: paths,
+        symbols: load_symbols(symbols)?,
+    })
+}
+
+fn load_symbols(path: Option<String>) -> Result<Symbols, String> {
+    match path {
+        Some(path) => Symbols::load(&path),
+        None => Ok(Symbols::default()),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got {}", option, value))
+}
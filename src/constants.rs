@@ -1,13 +1,16 @@
-pub const CHIP8_MEMORY: usize = 4096;
 pub const CHIP8_VIDEO_WIDTH: usize = 64;
 pub const CHIP8_VIDEO_HEIGHT: usize = 32;
 pub const STACK_HEIGHT: usize = 16;
 pub const REGISTERS_V: usize = 16;
 pub const KEYPAD_SIZE: usize = 16;
+pub const USER_FLAGS: usize = 8; // SUPER-CHIP RPL user flags; XO-CHIP has 16
 
 pub const VIDEO_SCALE: usize = 20;
 
 // Timing constants
-pub const CPU_HZ: u32 = 500; // CPU cycles per second
-pub const TIMER_HZ: u32 = 60; // Timer ticks per second
-pub const CYCLES_PER_TIMER_TICK: u32 = CPU_HZ / TIMER_HZ; // ~11-12 cycles per timer tick
\ No newline at end of file
+pub const FRAME_HZ: u32 = 60; // Frames per second, timers tick once per frame
+pub const INSTRUCTIONS_PER_FRAME: u32 = 8; // Default speed, ~500 instructions per second
+pub const MAX_CATCHUP_FRAMES: u32 = 5; // Frames run at once to catch up after a stall
+
+// Analog stick travel (out of 32767) before it counts as a direction
+pub const CONTROLLER_DEADZONE: i16 = 8000;
@@ -0,0 +1,236 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+
+use crate::rom_database::GameKeys;
+use crate::{CONTROLLER_DEADZONE, KEYPAD_SIZE};
+
+/// Button-to-key mapping for game controllers.
+pub struct ControllerMapping {
+    buttons: HashMap<Button, u8>,
+    deadzone: i16,
+}
+
+impl ControllerMapping {
+    /// Load the mapping for a ROM from `<rom>.pad` if it exists, else use the default.
+    ///
+    /// The file holds `button = key` lines, where `button` is an SDL controller
+    /// button name (`a`, `dpup`, `leftshoulder`, ...) and `key` a hex keypad digit.
+    /// A `deadzone = <0-32767>` line sets the analog stick deadzone.
+    /// A game's direction and action keys go on the D-pad, A and B in between.
+    pub fn for_rom(rom_name: &str, game_keys: GameKeys) -> Result<ControllerMapping, String> {
+        let mut mapping = ControllerMapping::default();
+
+        let game_bindings = [
+            (Button::DPadUp, game_keys.up),
+            (Button::DPadDown, game_keys.down),
+            (Button::DPadLeft, game_keys.left),
+            (Button::DPadRight, game_keys.right),
+            (Button::A, game_keys.a),
+            (Button::B, game_keys.b),
+        ];
+        for (button, key) in game_bindings {
+            if let Some(key) = key {
+                mapping.buttons.insert(button, key);
+            }
+        }
+
+        let path = format!("{}.pad", rom_name);
+        if !Path::new(&path).exists() {
+            return Ok(mapping);
+        }
+
+        let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let location = format!("{}:{}", path, line_number + 1);
+            let (name, value) = line
+                .split_once('=')
+                .map(|(name, value)| (name.trim(), value.trim()))
+                .ok_or(format!("{}: expected `button = key`", location))?;
+
+            if name == "deadzone" {
+                mapping.deadzone = value
+                    .parse()
+                    .ok()
+                    .filter(|deadzone| (0..=i16::MAX).contains(deadzone))
+                    .ok_or(format!(
+                        "{}: invalid deadzone {} (0-32767)",
+                        location, value
+                    ))?;
+                continue;
+            }
+
+            let button = Button::from_string(name)
+                .ok_or(format!("{}: unknown button {}", location, name))?;
+            let key = u8::from_str_radix(value, 16)
+                .ok()
+                .filter(|&key| (key as usize) < KEYPAD_SIZE)
+                .ok_or(format!("{}: invalid key {}", location, value))?;
+
+            mapping.buttons.insert(button, key);
+        }
+
+        Ok(mapping)
+    }
+
+    fn key(&self, button: Button) -> Option<u8> {
+        self.buttons.get(&button).copied()
+    }
+}
+
+impl Default for ControllerMapping {
+    /// D-pad on 5/7/8/9 (the WASD block), face buttons on the keys around it.
+    fn default() -> ControllerMapping {
+        let buttons = HashMap::from([
+            (Button::DPadUp, 0x5),
+            (Button::DPadLeft, 0x7),
+            (Button::DPadDown, 0x8),
+            (Button::DPadRight, 0x9),
+            (Button::A, 0x6),
+            (Button::B, 0x4),
+            (Button::X, 0x1),
+            (Button::Y, 0x2),
+            (Button::LeftShoulder, 0x3),
+            (Button::RightShoulder, 0xC),
+            (Button::Back, 0x0),
+            (Button::Start, 0xF),
+        ]);
+
+        ControllerMapping {
+            buttons,
+            deadzone: CONTROLLER_DEADZONE,
+        }
+    }
+}
+
+pub struct ControllerDriver {
+    subsystem: GameControllerSubsystem,
+    controllers: HashMap<u32, GameController>, // Open controllers by instance id
+    mapping: ControllerMapping,
+    held: HashSet<(u32, Button, bool)>, // Held inputs: (instance id, button, from stick)
+}
+
+impl ControllerDriver {
+    pub fn new(
+        sdl_context: &sdl2::Sdl,
+        mapping: ControllerMapping,
+    ) -> Result<ControllerDriver, String> {
+        let subsystem = sdl_context.game_controller()?;
+
+        Ok(ControllerDriver {
+            subsystem,
+            controllers: HashMap::new(),
+            mapping,
+            held: HashSet::new(),
+        })
+    }
+
+    /// Switch to another ROM's mapping when a new ROM is loaded.
+    pub fn set_mapping(&mut self, mapping: ControllerMapping) {
+        self.mapping = mapping;
+        self.held.clear();
+    }
+
+    /// Handle a controller event, returning the keypad changes it causes.
+    ///
+    /// Controllers already connected at startup arrive as device-added events too.
+    pub fn handle_event(&mut self, event: &Event) -> Vec<(u8, bool)> {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                if let Ok(controller) = self.subsystem.open(which) {
+                    println!("Controller connected: {}", controller.name());
+                    self.controllers
+                        .insert(controller.instance_id(), controller);
+                }
+                Vec::new()
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(controller) = self.controllers.remove(&which) {
+                    println!("Controller disconnected: {}", controller.name());
+                }
+                self.release_all(which)
+            }
+            Event::ControllerButtonDown { which, button, .. } => {
+                self.set_input(which, button, false, true)
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                self.set_input(which, button, false, false)
+            }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => self.axis(which, axis, value),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Treat the left stick as a second D-pad once it leaves the deadzone.
+    fn axis(&mut self, which: u32, axis: Axis, value: i16) -> Vec<(u8, bool)> {
+        let (negative, positive) = match axis {
+            Axis::LeftX => (Button::DPadLeft, Button::DPadRight),
+            Axis::LeftY => (Button::DPadUp, Button::DPadDown),
+            _ => return Vec::new(),
+        };
+
+        let deadzone = self.mapping.deadzone;
+        let mut changes = self.set_input(which, negative, true, value < -deadzone);
+        changes.extend(self.set_input(which, positive, true, value > deadzone));
+        changes
+    }
+
+    fn release_all(&mut self, which: u32) -> Vec<(u8, bool)> {
+        let inputs: Vec<(u32, Button, bool)> = self
+            .held
+            .iter()
+            .filter(|&&(id, _, _)| id == which)
+            .copied()
+            .collect();
+
+        inputs
+            .into_iter()
+            .flat_map(|(id, button, stick)| self.set_input(id, button, stick, false))
+            .collect()
+    }
+
+    fn set_input(
+        &mut self,
+        which: u32,
+        button: Button,
+        stick: bool,
+        active: bool,
+    ) -> Vec<(u8, bool)> {
+        let Some(key) = self.mapping.key(button) else {
+            return Vec::new();
+        };
+
+        // A key stays down while any button or stick direction mapped to it is held
+        let was_down = self.key_down(key);
+        if active {
+            self.held.insert((which, button, stick));
+        } else {
+            self.held.remove(&(which, button, stick));
+        }
+        let is_down = self.key_down(key);
+
+        if was_down != is_down {
+            vec![(key, is_down)]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn key_down(&self, key: u8) -> bool {
+        self.held
+            .iter()
+            .any(|&(_, button, _)| self.mapping.key(button) == Some(key))
+    }
+}
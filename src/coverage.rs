@@ -0,0 +1,279 @@
+//! Which instructions of a ROM ran, and which way its skips went.
+//!
+//! Coverage files are text, so runs can be merged and checked in:
+//!
+//! ```text
+//! rom 0123456789abcdef0123456789abcdef01234567
+//! load 0x200
+//! runs 3
+//! 0206 777 1 776
+//! ```
+//!
+//! Each address line holds the executions and, for skip instructions, how
+//! often the skip was taken and not taken.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+
+use crate::detect;
+use crate::disassembler;
+use crate::rom_database::rom_hash;
+use crate::symbols::Symbols;
+
+// Bytes of data shown per line of the listing
+const DATA_PER_LINE: usize = 8;
+
+/// Execution counts for the addresses of one ROM, collected while a `Chip8`
+/// runs or loaded from coverage files.
+pub struct Coverage {
+    rom_hash: String,
+    load_address: u16,
+    runs: u64,                         // Runs merged into these counts
+    executed: BTreeMap<u16, u64>,      // Executions per address
+    branches: BTreeMap<u16, [u64; 2]>, // Skips per address: taken, not taken
+}
+
+impl Coverage {
+    pub fn new(rom: &[u8], load_address: u16) -> Coverage {
+        Coverage {
+            rom_hash: rom_hash(rom),
+            load_address,
+            runs: 1,
+            executed: BTreeMap::new(),
+            branches: BTreeMap::new(),
+        }
+    }
+
+    /// Count an instruction at `pc` that continued at `next_pc`.
+    pub fn record(&mut self, pc: u16, opcode: u16, next_pc: u16) {
+        *self.executed.entry(pc).or_default() += 1;
+        if disassembler::is_skip(opcode) {
+            let taken = next_pc != pc.wrapping_add(2);
+            self.branches.entry(pc).or_default()[usize::from(!taken)] += 1;
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Coverage, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read coverage {}: {}", path, e))?;
+
+        let mut coverage = Coverage {
+            rom_hash: String::new(),
+            load_address: 0,
+            runs: 0,
+            executed: BTreeMap::new(),
+            branches: BTreeMap::new(),
+        };
+        for (line_number, line) in contents.lines().enumerate() {
+            let location = format!("{}:{}", path, line_number + 1);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let number = |value: &str| {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("{}: invalid count {}", location, value))
+            };
+
+            match fields[..] {
+                [] => {}
+                [comment, ..] if comment.starts_with('#') => {}
+                ["rom", hash] => coverage.rom_hash = hash.to_string(),
+                ["load", address] => {
+                    coverage.load_address =
+                        u16::from_str_radix(address.trim_start_matches("0x"), 16).map_err(
+                            |_| format!("{}: invalid load address {}", location, address),
+                        )?;
+                }
+                ["runs", runs] => coverage.runs = number(runs)?,
+                [address, executed, ref branches @ ..] if matches!(branches.len(), 0 | 2) => {
+                    let address = u16::from_str_radix(address, 16)
+                        .map_err(|_| format!("{}: invalid address {}", location, address))?;
+                    coverage.executed.insert(address, number(executed)?);
+                    if let [taken, not_taken] = branches {
+                        coverage
+                            .branches
+                            .insert(address, [number(taken)?, number(not_taken)?]);
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "{}: expected `address executed [taken not-taken]`",
+                        location
+                    ));
+                }
+            }
+        }
+
+        if coverage.rom_hash.is_empty() {
+            return Err(format!("{} is not a coverage file", path));
+        }
+        Ok(coverage)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut lines = vec![
+            format!("rom {}", self.rom_hash),
+            format!("load {:#05x}", self.load_address),
+            format!("runs {}", self.runs),
+        ];
+        for (&address, &executed) in &self.executed {
+            match self.branches.get(&address) {
+                Some([taken, not_taken]) => lines.push(format!(
+                    "{:04X} {} {} {}",
+                    address, executed, taken, not_taken
+                )),
+                None => lines.push(format!("{:04X} {}", address, executed)),
+            }
+        }
+        fs::write(path, lines.join("\n") + "\n")
+            .map_err(|e| format!("Could not write coverage {}: {}", path, e))
+    }
+
+    /// Add the counts of another run of the same ROM.
+    pub fn merge(&mut self, other: &Coverage) -> Result<(), String> {
+        if other.rom_hash != self.rom_hash || other.load_address != self.load_address {
+            return Err("Coverage is for a different ROM".to_string());
+        }
+        self.runs += other.runs;
+        for (&address, &executed) in &other.executed {
+            *self.executed.entry(address).or_default() += executed;
+        }
+        for (&address, &[taken, not_taken]) in &other.branches {
+            let branch = self.branches.entry(address).or_default();
+            branch[0] += taken;
+            branch[1] += not_taken;
+        }
+        Ok(())
+    }
+
+    pub fn is_for(&self, rom: &[u8]) -> bool {
+        self.rom_hash == rom_hash(rom)
+    }
+
+    /// One line with the share of instructions and skip outcomes covered.
+    pub fn summary(&self, rom: &[u8]) -> String {
+        let code = self.code(rom);
+        let covered = code
+            .iter()
+            .filter(|address| self.executed.contains_key(address))
+            .count();
+        let skips: Vec<u16> = code
+            .iter()
+            .copied()
+            .filter(|&address| disassembler::is_skip(opcode_at(rom, self.offset(address))))
+            .collect();
+        let both_ways = skips
+            .iter()
+            .filter(|address| {
+                self.branches
+                    .get(address)
+                    .is_some_and(|b| b[0] > 0 && b[1] > 0)
+            })
+            .count();
+
+        format!(
+            "Coverage over {} runs: {} of {} instructions ({:.1}%), {} of {} skips went both ways",
+            self.runs,
+            covered,
+            code.len(),
+            100.0 * covered as f64 / code.len().max(1) as f64,
+            both_ways,
+            skips.len()
+        )
+    }
+
+    /// Disassembly of the ROM with execution counts. Lines of code that never
+    /// ran are marked `!`, skips that only ever went one way `?`. Labels from
+    /// `symbols` head the lines they name.
+    pub fn listing(&self, rom: &[u8], symbols: &Symbols) -> String {
+        let code = self.code(rom);
+        let start = self.load_address as usize;
+        let end = start + rom.len();
+        let mut lines = vec![self.summary(rom), String::new()];
+
+        let mut address = start;
+        while address < end {
+            let offset = address - start;
+            if let Some(label) = symbols.label(address as u16) {
+                lines.push(format!("{}:", label));
+            }
+            if !code.contains(&(address as u16)) || offset + 1 >= rom.len() {
+                // Data up to the next instruction or label
+                let next_label = symbols.next_label(address as u16);
+                let next_code = code
+                    .range(address as u16 + 1..)
+                    .next()
+                    .map_or(end, |&next| next as usize)
+                    .min(next_label.map_or(end, |next| next as usize))
+                    .min(address + DATA_PER_LINE);
+                let bytes: Vec<String> = rom[offset..next_code - start]
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect();
+                lines.push(format!("  {:04X}  {}", address, bytes.join(" ")));
+                address = next_code;
+                continue;
+            }
+
+            let opcode = opcode_at(rom, offset);
+            let executed = self.executed.get(&(address as u16)).copied().unwrap_or(0);
+            let branch = self
+                .branches
+                .get(&(address as u16))
+                .copied()
+                .unwrap_or_default();
+            let (marker, note) = if executed == 0 {
+                ("!", "never executed".to_string())
+            } else if disassembler::is_skip(opcode) {
+                let marker = if branch[0] == 0 || branch[1] == 0 {
+                    "?"
+                } else {
+                    " "
+                };
+                (
+                    marker,
+                    format!(
+                        "x{}, taken {}, not taken {}",
+                        executed, branch[0], branch[1]
+                    ),
+                )
+            } else {
+                (" ", format!("x{}", executed))
+            };
+
+            lines.push(format!(
+                "{} {:04X}  {:04X}  {:<20} {}",
+                marker,
+                address,
+                opcode,
+                disassembler::mnemonic(opcode, symbols),
+                note
+            ));
+            address += if opcode == 0xF000 { 4 } else { 2 };
+        }
+
+        lines.join("\n")
+    }
+
+    /// Addresses of instructions: ones that ran and ones reachable from the
+    /// start of the program.
+    fn code(&self, rom: &[u8]) -> BTreeSet<u16> {
+        let start = self.load_address as usize;
+        let in_rom = |address: usize| (start..start + rom.len()).contains(&address);
+
+        detect::reachable_code(rom, start)
+            .into_iter()
+            .chain(self.executed.keys().map(|&address| address as usize))
+            .filter(|&address| in_rom(address))
+            .map(|address| address as u16)
+            .collect()
+    }
+
+    fn offset(&self, address: u16) -> usize {
+        (address - self.load_address) as usize
+    }
+}
+
+fn opcode_at(rom: &[u8], offset: usize) -> u16 {
+    let byte = |offset: usize| rom.get(offset).copied().unwrap_or(0) as u16;
+    (byte(offset) << 8) | byte(offset + 1)
+}
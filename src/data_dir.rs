@@ -0,0 +1,20 @@
+//! Where the emulator keeps files between runs.
+
+use std::env;
+use std::path::PathBuf;
+
+/// Directory for files the emulator keeps between runs, following each
+/// system's convention for per-user application data.
+pub fn user_data_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+    };
+    base.map(|dir| dir.join("chip8-emulator"))
+}
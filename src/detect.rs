@@ -0,0 +1,182 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::disassembler::is_skip;
+use crate::platform::Platform;
+
+// Address programs are loaded at and the most a CHIP-8 or SUPER-CHIP program can hold
+const PROGRAM_START: usize = 0x200;
+const MAX_CHIP8_ROM_SIZE: usize = 3584;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        })
+    }
+}
+
+/// Platform guessed from a ROM's contents.
+pub struct Detection {
+    pub platform: Platform,
+    pub confidence: Confidence,
+    pub evidence: Vec<String>, // Why the platform was picked
+}
+
+/// Guess the platform a ROM was written for from its size and the opcodes in
+/// its reachable code. Quirks follow from `Platform::quirks`.
+///
+/// Code is found by following jumps, calls and skips from the entry point, so
+/// sprite data that happens to look like an extension opcode is not counted.
+/// Computed jumps (Bnnn) can't be followed and lower the confidence.
+pub fn detect_platform(rom: &[u8]) -> Detection {
+    if rom.len() > MAX_CHIP8_ROM_SIZE {
+        return Detection {
+            platform: Platform::XoChip,
+            confidence: Confidence::High,
+            evidence: vec![format!(
+                "{} bytes, more than the {} that fit in CHIP-8 memory",
+                rom.len(),
+                MAX_CHIP8_ROM_SIZE
+            )],
+        };
+    }
+
+    let scan = scan_reachable(rom, PROGRAM_START);
+    let found = |platform| -> Vec<String> {
+        scan.extensions
+            .iter()
+            .filter(|(extension, _)| *extension == platform)
+            .map(|(_, opcode)| opcode.to_string())
+            .collect()
+    };
+
+    let xochip = found(Platform::XoChip);
+    let schip = found(Platform::SuperChip);
+    let (platform, evidence) = if !xochip.is_empty() {
+        (Platform::XoChip, xochip)
+    } else if !schip.is_empty() {
+        (Platform::SuperChip, schip)
+    } else {
+        let evidence = vec!["no extension opcodes in reachable code".to_string()];
+        let confidence = if scan.computed_jump {
+            Confidence::Low
+        } else {
+            Confidence::Medium
+        };
+        return Detection {
+            platform: Platform::Chip8,
+            confidence,
+            evidence,
+        };
+    };
+
+    // A single opcode may still be data reached through a misread skip
+    let confidence = if evidence.len() > 1 {
+        Confidence::High
+    } else {
+        Confidence::Medium
+    };
+
+    Detection {
+        platform,
+        confidence,
+        evidence,
+    }
+}
+
+/// Addresses of the instructions reachable from the start of a ROM loaded at
+/// `start`, found by following jumps, calls and skips as for detection.
+pub fn reachable_code(rom: &[u8], start: usize) -> BTreeSet<usize> {
+    scan_reachable(rom, start).code
+}
+
+struct Scan {
+    extensions: BTreeSet<(Platform, &'static str)>, // Extension opcodes seen
+    computed_jump: bool,                            // Hit a Bnnn that couldn't be followed
+    code: BTreeSet<usize>,                          // Addresses of reachable instructions
+}
+
+fn scan_reachable(rom: &[u8], start: usize) -> Scan {
+    let opcode_at = |address: usize| -> Option<u16> {
+        let offset = address.checked_sub(start)?;
+        let bytes = rom.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+    // F000 nnnn is the only four byte instruction
+    let length_at = |address: usize| {
+        if opcode_at(address) == Some(0xF000) {
+            4
+        } else {
+            2
+        }
+    };
+
+    let mut scan = Scan {
+        extensions: BTreeSet::new(),
+        computed_jump: false,
+        code: BTreeSet::new(),
+    };
+    let mut pending = vec![start];
+
+    while let Some(address) = pending.pop() {
+        let Some(opcode) = opcode_at(address) else {
+            continue;
+        };
+        if !scan.code.insert(address) {
+            continue;
+        }
+
+        if let Some(extension) = extension_opcode(opcode) {
+            scan.extensions.insert(extension);
+        }
+
+        let next = address + length_at(address);
+        let target = (opcode & 0x0FFF) as usize;
+        match opcode {
+            0x00EE | 0x00FD => {}
+            _ if opcode & 0xF000 == 0x1000 => pending.push(target),
+            _ if opcode & 0xF000 == 0x2000 => pending.extend([target, next]),
+            _ if opcode & 0xF000 == 0xB000 => scan.computed_jump = true,
+            _ if is_skip(opcode) => pending.extend([next, next + length_at(next)]),
+            _ => pending.push(next),
+        }
+    }
+
+    scan
+}
+
+/// The platform an opcode first appeared on, if it isn't plain CHIP-8.
+fn extension_opcode(opcode: u16) -> Option<(Platform, &'static str)> {
+    let xochip = |name| Some((Platform::XoChip, name));
+    let schip = |name| Some((Platform::SuperChip, name));
+
+    match opcode {
+        0xF000 => xochip("F000 (load long I)"),
+        0xF002 => xochip("F002 (audio pattern)"),
+        _ if opcode & 0xF00F == 0x5002 => xochip("5XY2 (save range)"),
+        _ if opcode & 0xF00F == 0x5003 => xochip("5XY3 (load range)"),
+        _ if opcode & 0xF0FF == 0xF001 => xochip("FN01 (select planes)"),
+        _ if opcode & 0xF0FF == 0xF03A => xochip("FX3A (pitch)"),
+        _ if opcode & 0xFFF0 == 0x00D0 => xochip("00DN (scroll up)"),
+        0x00FB => schip("00FB (scroll right)"),
+        0x00FC => schip("00FC (scroll left)"),
+        0x00FD => schip("00FD (exit)"),
+        0x00FE => schip("00FE (low resolution)"),
+        0x00FF => schip("00FF (high resolution)"),
+        _ if opcode & 0xFFF0 == 0x00C0 => schip("00CN (scroll down)"),
+        _ if opcode & 0xF0FF == 0xF030 => schip("FX30 (big font)"),
+        _ if opcode & 0xF0FF == 0xF075 => schip("FX75 (save flags)"),
+        _ if opcode & 0xF0FF == 0xF085 => schip("FX85 (load flags)"),
+        _ => None,
+    }
+}
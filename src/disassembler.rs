@@ -0,0 +1,165 @@
+//! Opcode mnemonics in the style of Cowgod's CHIP-8 reference, with the
+//! SUPER-CHIP and XO-CHIP additions.
+
+use crate::symbols::Symbols;
+
+/// Assembly text for an opcode. Words that aren't instructions come out as
+/// `DW` data. Addresses are shown as labels where `symbols` has one.
+pub fn mnemonic(opcode: u16, symbols: &Symbols) -> String {
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    let n = opcode & 0xF;
+    let kk = opcode & 0xFF;
+    let nnn = || {
+        symbols
+            .location(opcode & 0xFFF)
+            .unwrap_or_else(|| format!("0x{:03X}", opcode & 0xFFF))
+    };
+
+    match opcode >> 12 {
+        0x0 => match opcode {
+            0x00E0 => "CLS".to_string(),
+            0x00EE => "RET".to_string(),
+            0x00FB => "SCR".to_string(),
+            0x00FC => "SCL".to_string(),
+            0x00FD => "EXIT".to_string(),
+            0x00FE => "LOW".to_string(),
+            0x00FF => "HIGH".to_string(),
+            _ if opcode & 0xFFF0 == 0x00C0 => format!("SCD {}", n),
+            _ if opcode & 0xFFF0 == 0x00D0 => format!("SCU {}", n),
+            _ => format!("SYS {}", nnn()),
+        },
+        0x1 => format!("JP {}", nnn()),
+        0x2 => format!("CALL {}", nnn()),
+        0x3 => format!("SE V{:X}, 0x{:02X}", x, kk),
+        0x4 => format!("SNE V{:X}, 0x{:02X}", x, kk),
+        0x5 => match n {
+            0x0 => format!("SE V{:X}, V{:X}", x, y),
+            0x2 => format!("SAVE V{:X}-V{:X}", x, y),
+            0x3 => format!("LOAD V{:X}-V{:X}", x, y),
+            _ => data(opcode),
+        },
+        0x6 => format!("LD V{:X}, 0x{:02X}", x, kk),
+        0x7 => format!("ADD V{:X}, 0x{:02X}", x, kk),
+        0x8 => match n {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => data(opcode),
+        },
+        0x9 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA => format!("LD I, {}", nnn()),
+        0xB => format!("JP V0, {}", nnn()),
+        0xC => format!("RND V{:X}, 0x{:02X}", x, kk),
+        0xD => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE if kk == 0x9E => format!("SKP V{:X}", x),
+        0xE if kk == 0xA1 => format!("SKNP V{:X}", x),
+        0xF => match kk {
+            0x00 if x == 0 => "LD I, LONG".to_string(),
+            0x01 => format!("PLANE {}", x),
+            0x02 if x == 0 => "AUDIO".to_string(),
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x30 => format!("LD HF, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x3A => format!("PITCH V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            0x75 => format!("LD R, V{:X}", x),
+            0x85 => format!("LD V{:X}, R", x),
+            _ => data(opcode),
+        },
+        _ => data(opcode),
+    }
+}
+
+/// Opcode pattern an instruction belongs to, like `8xy4` or `Fx07`, for
+/// grouping statistics.
+pub fn opcode_class(opcode: u16) -> &'static str {
+    match opcode >> 12 {
+        0x0 => match opcode {
+            0x00E0 => "00E0",
+            0x00EE => "00EE",
+            0x00FB => "00FB",
+            0x00FC => "00FC",
+            0x00FD => "00FD",
+            0x00FE => "00FE",
+            0x00FF => "00FF",
+            _ if opcode & 0xFFF0 == 0x00C0 => "00Cn",
+            _ if opcode & 0xFFF0 == 0x00D0 => "00Dn",
+            _ => "0nnn",
+        },
+        0x1 => "1nnn",
+        0x2 => "2nnn",
+        0x3 => "3xkk",
+        0x4 => "4xkk",
+        0x5 => match opcode & 0xF {
+            0x0 => "5xy0",
+            0x2 => "5xy2",
+            0x3 => "5xy3",
+            _ => "data",
+        },
+        0x6 => "6xkk",
+        0x7 => "7xkk",
+        0x8 => match opcode & 0xF {
+            0x0 => "8xy0",
+            0x1 => "8xy1",
+            0x2 => "8xy2",
+            0x3 => "8xy3",
+            0x4 => "8xy4",
+            0x5 => "8xy5",
+            0x6 => "8xy6",
+            0x7 => "8xy7",
+            0xE => "8xyE",
+            _ => "data",
+        },
+        0x9 if opcode & 0xF == 0 => "9xy0",
+        0xA => "Annn",
+        0xB => "Bnnn",
+        0xC => "Cxkk",
+        0xD => "Dxyn",
+        0xE if opcode & 0xFF == 0x9E => "Ex9E",
+        0xE if opcode & 0xFF == 0xA1 => "ExA1",
+        0xF => match opcode & 0xFF {
+            0x00 if opcode == 0xF000 => "F000",
+            0x01 => "Fn01",
+            0x02 if opcode == 0xF002 => "F002",
+            0x07 => "Fx07",
+            0x0A => "Fx0A",
+            0x15 => "Fx15",
+            0x18 => "Fx18",
+            0x1E => "Fx1E",
+            0x29 => "Fx29",
+            0x30 => "Fx30",
+            0x33 => "Fx33",
+            0x3A => "Fx3A",
+            0x55 => "Fx55",
+            0x65 => "Fx65",
+            0x75 => "Fx75",
+            0x85 => "Fx85",
+            _ => "data",
+        },
+        _ => "data",
+    }
+}
+
+/// Whether an opcode conditionally skips the next instruction.
+pub fn is_skip(opcode: u16) -> bool {
+    matches!(opcode & 0xF000, 0x3000 | 0x4000)
+        || matches!(opcode & 0xF00F, 0x5000 | 0x9000)
+        || matches!(opcode & 0xF0FF, 0xE09E | 0xE0A1)
+}
+
+fn data(opcode: u16) -> String {
+    format!("DW 0x{:04X}", opcode)
+}
@@ -1,60 +1,146 @@
-use sdl2;
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::{FullscreenType, Window};
 
-use crate::{CHIP8_VIDEO_HEIGHT, CHIP8_VIDEO_WIDTH, VIDEO_SCALE};
+use crate::display_filter::DisplayFilter;
+use crate::palette::Palette;
+use crate::{CHIP8_VIDEO_HEIGHT, CHIP8_VIDEO_WIDTH};
 
 pub struct DisplayDriver {
     canvas: Canvas<Window>,
-    scale: u32,
+    texture: Texture,       // Streaming texture holding one emulated pixel per texel
+    resolution: (u32, u32), // Emulated resolution the canvas is laid out for
+    palette: Palette,
+    filter: DisplayFilter,
 }
 
 impl DisplayDriver {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Result<DisplayDriver, String> {
+    pub fn new(
+        sdl_context: &sdl2::Sdl,
+        palette: Palette,
+        filter: DisplayFilter,
+        scale: u32,
+        integer_scale: bool,
+        vsync: bool,
+    ) -> Result<DisplayDriver, String> {
         let video_subsystem = sdl_context.video()?;
         let window = video_subsystem
             .window(
                 "CHIP-8 Emulator",
-                (CHIP8_VIDEO_WIDTH * VIDEO_SCALE) as u32,
-                (CHIP8_VIDEO_HEIGHT * VIDEO_SCALE) as u32,
+                CHIP8_VIDEO_WIDTH as u32 * scale,
+                CHIP8_VIDEO_HEIGHT as u32 * scale,
             )
             .position_centered()
+            .resizable()
+            .allow_highdpi()
             .build()
             .map_err(|e| e.to_string())?;
 
-        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        let mut canvas_builder = window.into_canvas();
+        if vsync {
+            canvas_builder = canvas_builder.present_vsync();
+        }
+        let mut canvas = canvas_builder.build().map_err(|e| e.to_string())?;
+
+        // Draw in emulated pixels and let SDL scale to the window, letterboxing
+        // to keep the aspect ratio. Works in physical pixels on HiDPI displays.
+        let resolution = (CHIP8_VIDEO_WIDTH as u32, CHIP8_VIDEO_HEIGHT as u32);
+        canvas
+            .set_logical_size(resolution.0, resolution.1)
+            .map_err(|e| e.to_string())?;
+        canvas.set_integer_scale(integer_scale)?;
+        let texture = canvas
+            .create_texture_streaming(PixelFormatEnum::RGB24, resolution.0, resolution.1)
+            .map_err(|e| e.to_string())?;
 
         Ok(DisplayDriver {
             canvas,
-            scale: VIDEO_SCALE as u32,
+            texture,
+            resolution,
+            palette,
+            filter,
         })
     }
 
-    pub fn draw_screen(&mut self, vram: &[[u8; CHIP8_VIDEO_WIDTH]; CHIP8_VIDEO_HEIGHT]) {
-        for (y, row) in vram.iter().enumerate() {
-            for (x, &pixel_value) in row.iter().enumerate() {
-                let x_size = x as u32 * self.scale;
-                let y_size = (y as u32) * self.scale;
-                
-                if pixel_value == 0 {
-                    self.canvas.set_draw_color(Color::BLACK);
-                } else {
-                    self.canvas.set_draw_color(Color::WHITE);
-                }
-                let _ = self.canvas.fill_rect(Rect::new(
-                    x_size as i32,
-                    y_size as i32,
-                    self.scale,
-                    self.scale,
-                ));
-            }
+    pub fn set_title(&mut self, title: &str) -> Result<(), String> {
+        self.canvas
+            .window_mut()
+            .set_title(title)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        window.set_fullscreen(fullscreen)
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn filter_mut(&mut self) -> &mut DisplayFilter {
+        &mut self.filter
+    }
+
+    /// Whether the filter needs more frames drawn to finish fading out.
+    pub fn is_animating(&self) -> bool {
+        self.filter.is_animating()
+    }
+
+    /// Upload a frame into the streaming texture and present it, scaled by SDL.
+    pub fn draw_screen<R: AsRef<[u8]>>(&mut self, vram: &[R]) -> Result<(), String> {
+        let width = vram.first().map_or(1, |row| row.as_ref().len().max(1));
+        let resolution = (width as u32, vram.len().max(1) as u32);
+
+        // Relayout when the program switches between lores and hires
+        if resolution != self.resolution {
+            let texture = self
+                .canvas
+                .create_texture_streaming(PixelFormatEnum::RGB24, resolution.0, resolution.1)
+                .map_err(|e| e.to_string())?;
+            let old_texture = std::mem::replace(&mut self.texture, texture);
+            // SAFETY: the canvas that owns the texture is still alive
+            unsafe { old_texture.destroy() };
+
+            self.canvas
+                .set_logical_size(resolution.0, resolution.1)
+                .map_err(|e| e.to_string())?;
+            self.resolution = resolution;
         }
+
+        let background = self.palette.color(0);
+        let palette = &self.palette;
+        let pixels = self.filter.apply(vram);
+        self.texture.with_lock(None, |buffer, pitch| {
+            for (index, &(pixel_value, intensity)) in pixels.iter().enumerate() {
+                let color = blend(background, palette.color(pixel_value), intensity);
+                let offset = (index / width) * pitch + (index % width) * 3;
+                buffer[offset..offset + 3].copy_from_slice(&[color.r, color.g, color.b]);
+            }
+        })?;
+
+        // Clear the letterbox bars
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, None)?;
         self.present();
+        Ok(())
     }
 
     pub fn present(&mut self) {
         self.canvas.present();
     }
 }
+
+fn blend(from: Color, to: Color, amount: f32) -> Color {
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * amount).round() as u8;
+    Color::RGB(mix(from.r, to.r), mix(from.g, to.g), mix(from.b, to.b))
+}
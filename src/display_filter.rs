@@ -0,0 +1,152 @@
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    Off,
+    Fade,  // Cleared pixels fade out over a few frames, like CRT phosphor
+    Blend, // Pixels lit in the previous frame stay visible for one more
+}
+
+/// Anti-flicker filter applied to each presented frame.
+///
+/// Produces a `(pixel value, intensity)` pair per pixel, where intensity blends
+/// from the background color (0.0) to the pixel's palette color (1.0).
+pub struct DisplayFilter {
+    mode: FilterMode,
+    strength: f32,
+    width: usize,
+    height: usize,
+    previous: Vec<u8>,      // Last frame's pixel values
+    output: Vec<(u8, f32)>, // Filtered pixels, row-major
+    animating: bool,        // Output still shows pixels that are no longer set
+}
+
+impl DisplayFilter {
+    pub fn new(mode: FilterMode, strength: f32) -> DisplayFilter {
+        DisplayFilter {
+            mode,
+            strength: strength.clamp(0.0, 1.0),
+            width: 0,
+            height: 0,
+            previous: Vec::new(),
+            output: Vec::new(),
+            animating: false,
+        }
+    }
+
+    /// Parse `off`, `fade`, `blend`, optionally with a strength like `fade:0.8`.
+    pub fn parse(spec: &str) -> Result<DisplayFilter, String> {
+        let (name, strength) = match spec.split_once(':') {
+            Some((name, strength)) => (
+                name,
+                Some(
+                    strength
+                        .parse::<f32>()
+                        .map_err(|_| format!("Invalid filter strength {}", strength))?,
+                ),
+            ),
+            None => (spec, None),
+        };
+
+        let mode = match name {
+            "off" => FilterMode::Off,
+            "fade" => FilterMode::Fade,
+            "blend" => FilterMode::Blend,
+            _ => return Err(format!("Unknown filter {} (off, fade, blend)", name)),
+        };
+        match strength {
+            // At full strength a fading pixel would never go dark
+            Some(strength) if mode == FilterMode::Fade && !(0.0..1.0).contains(&strength) => {
+                return Err(format!(
+                    "Fade strength {} must be from 0 to below 1",
+                    strength
+                ));
+            }
+            Some(strength) if !(0.0..=1.0).contains(&strength) => {
+                return Err(format!("Filter strength {} must be from 0 to 1", strength));
+            }
+            _ => {}
+        }
+
+        Ok(DisplayFilter::new(
+            mode,
+            strength.unwrap_or(default_strength(mode)),
+        ))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.mode {
+            FilterMode::Off => "off",
+            FilterMode::Fade => "fade",
+            FilterMode::Blend => "blend",
+        }
+    }
+
+    /// Switch to the next mode at its default strength.
+    pub fn cycle_mode(&mut self) {
+        let mode = match self.mode {
+            FilterMode::Off => FilterMode::Fade,
+            FilterMode::Fade => FilterMode::Blend,
+            FilterMode::Blend => FilterMode::Off,
+        };
+        *self = DisplayFilter::new(mode, default_strength(mode));
+    }
+
+    /// Whether pixels are still fading, so the screen changes without new draws.
+    pub fn is_animating(&self) -> bool {
+        self.animating
+    }
+
+    /// Filter a frame of any resolution.
+    pub fn apply<R: AsRef<[u8]>>(&mut self, vram: &[R]) -> &[(u8, f32)] {
+        let height = vram.len();
+        let width = vram.first().map_or(0, |row| row.as_ref().len());
+
+        // Start over when switching between resolutions
+        if width != self.width || height != self.height {
+            self.width = width;
+            self.height = height;
+            self.previous = vec![0; width * height];
+            self.output = vec![(0, 1.0); width * height];
+        }
+
+        self.animating = false;
+        let pixels = vram.iter().flat_map(|row| row.as_ref().iter().copied());
+        for (index, pixel) in pixels.enumerate() {
+            self.output[index] = match self.mode {
+                _ if pixel != 0 => (pixel, 1.0),
+                FilterMode::Off => (0, 1.0),
+                FilterMode::Fade => {
+                    let (value, intensity) = self.output[index];
+                    let intensity = intensity * self.strength;
+                    // Snap to background once the glow is no longer visible
+                    if value == 0 || intensity < 0.05 {
+                        (0, 1.0)
+                    } else {
+                        (value, intensity)
+                    }
+                }
+                FilterMode::Blend => match self.previous[index] {
+                    0 => (0, 1.0),
+                    previous => (previous, self.strength),
+                },
+            };
+            self.animating |= pixel == 0 && self.output[index].0 != 0;
+            self.previous[index] = pixel;
+        }
+
+        &self.output
+    }
+}
+
+impl Default for DisplayFilter {
+    fn default() -> DisplayFilter {
+        DisplayFilter::new(FilterMode::Off, 0.0)
+    }
+}
+
+fn default_strength(mode: FilterMode) -> f32 {
+    match mode {
+        FilterMode::Off => 0.0,
+        FilterMode::Fade => 0.6,
+        FilterMode::Blend => 0.5,
+    }
+}
@@ -0,0 +1,300 @@
+//! Minimal GIF decoder: just enough to get at the color indices of each frame.
+
+/// Whether the data starts with a GIF signature.
+pub fn is_gif(data: &[u8]) -> bool {
+    data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")
+}
+
+/// A decoded image, as palette indices in row-major order.
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// Decode every image in a GIF file, in file order. Frames aren't composited
+/// onto each other; each holds only its own pixels.
+pub fn decode_frames(data: &[u8]) -> Result<Vec<Frame>, String> {
+    if !is_gif(data) {
+        return Err("Not a GIF file".to_string());
+    }
+
+    let mut reader = Reader { data, position: 6 };
+    reader.skip(4)?; // Logical screen width and height
+    let flags = reader.byte()?;
+    reader.skip(2)?; // Background color and aspect ratio
+    skip_color_table(&mut reader, flags)?;
+
+    let mut frames = Vec::new();
+    loop {
+        match reader.byte()? {
+            // Extension: label, then data sub-blocks
+            0x21 => {
+                reader.byte()?;
+                reader.sub_blocks()?;
+            }
+            0x2C => frames.push(decode_image(&mut reader)?),
+            0x3B => return Ok(frames),
+            block => return Err(format!("Unknown GIF block {:#04x}", block)),
+        }
+    }
+}
+
+fn decode_image(reader: &mut Reader) -> Result<Frame, String> {
+    reader.skip(4)?; // Left and top
+    let width = reader.u16()? as usize;
+    let height = reader.u16()? as usize;
+    let flags = reader.byte()?;
+    skip_color_table(reader, flags)?;
+
+    let min_code_size = reader.byte()?;
+    if !(1..=11).contains(&min_code_size) {
+        return Err(format!("Invalid LZW code size {}", min_code_size));
+    }
+    let compressed = reader.sub_blocks()?;
+
+    let mut pixels = decompress(&compressed, min_code_size, width * height)?;
+    if pixels.len() < width * height {
+        return Err("GIF image data is truncated".to_string());
+    }
+
+    if flags & 0x40 != 0 {
+        pixels = deinterlace(&pixels, width, height);
+    }
+
+    Ok(Frame {
+        width,
+        height,
+        pixels,
+    })
+}
+
+fn skip_color_table(reader: &mut Reader, flags: u8) -> Result<(), String> {
+    if flags & 0x80 != 0 {
+        reader.skip(3 << ((flags & 0x07) + 1))?;
+    }
+    Ok(())
+}
+
+/// Variable-width LZW as used by GIF, with codes packed least significant bit first.
+fn decompress(data: &[u8], min_code_size: u8, limit: usize) -> Result<Vec<u8>, String> {
+    const MAX_CODES: usize = 4096;
+
+    let clear = 1usize << min_code_size;
+    let end = clear + 1;
+
+    // Each code is a previous code (its prefix) followed by one more index
+    let mut prefix = vec![0u16; MAX_CODES];
+    let mut suffix = vec![0u8; MAX_CODES];
+    let mut first = vec![0u8; MAX_CODES]; // First index of each code's string
+    for code in 0..clear {
+        suffix[code] = code as u8;
+        first[code] = code as u8;
+    }
+
+    // Grown as codes are decoded, as the header's size may be far beyond the data
+    let mut output = Vec::new();
+    let mut string = Vec::new();
+    let mut code_size = min_code_size as u32 + 1;
+    let mut next_code = end + 1;
+    let mut previous: Option<usize> = None;
+
+    let mut bits = 0u32;
+    let mut bit_count = 0u32;
+    let mut bytes = data.iter();
+
+    while output.len() < limit {
+        while bit_count < code_size {
+            let Some(&byte) = bytes.next() else {
+                return Ok(output);
+            };
+            bits |= (byte as u32) << bit_count;
+            bit_count += 8;
+        }
+        let code = (bits & ((1 << code_size) - 1)) as usize;
+        bits >>= code_size;
+        bit_count -= code_size;
+
+        if code == clear {
+            code_size = min_code_size as u32 + 1;
+            next_code = end + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            break;
+        }
+
+        let Some(previous_code) = previous else {
+            if code >= clear {
+                return Err(format!("Invalid first LZW code {}", code));
+            }
+            output.push(code as u8);
+            previous = Some(code);
+            continue;
+        };
+
+        // A code one past the table repeats the previous string plus its first index
+        let string_code = match code {
+            _ if code < next_code => code,
+            _ if code == next_code => previous_code,
+            _ => return Err(format!("Invalid LZW code {}", code)),
+        };
+
+        string.clear();
+        let mut current = string_code;
+        while current >= clear {
+            string.push(suffix[current]);
+            current = prefix[current] as usize;
+        }
+        string.push(current as u8);
+        string.reverse();
+        if code == next_code {
+            string.push(first[previous_code]);
+        }
+        output.extend_from_slice(&string);
+
+        if next_code < MAX_CODES {
+            prefix[next_code] = previous_code as u16;
+            suffix[next_code] = string[0];
+            first[next_code] = first[previous_code];
+            next_code += 1;
+            if next_code == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        }
+        previous = Some(code);
+    }
+
+    output.truncate(limit);
+    Ok(output)
+}
+
+/// Reorder rows stored in the 4-pass interlaced order into top-to-bottom order.
+fn deinterlace(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut rows = (0..height).step_by(8).collect::<Vec<_>>();
+    rows.extend((4..height).step_by(8));
+    rows.extend((2..height).step_by(4));
+    rows.extend((1..height).step_by(2));
+
+    let mut output = vec![0; pixels.len()];
+    for (source, &row) in rows.iter().enumerate() {
+        output[row * width..(row + 1) * width]
+            .copy_from_slice(&pixels[source * width..(source + 1) * width]);
+    }
+    output
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self
+            .data
+            .get(self.position)
+            .ok_or("GIF file ends unexpectedly")?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes([self.byte()?, self.byte()?]))
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), String> {
+        if self.position + count > self.data.len() {
+            return Err("GIF file ends unexpectedly".to_string());
+        }
+        self.position += count;
+        Ok(())
+    }
+
+    /// Concatenate length-prefixed sub-blocks up to the empty terminator.
+    fn sub_blocks(&mut self) -> Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+        loop {
+            let length = self.byte()? as usize;
+            if length == 0 {
+                return Ok(data);
+            }
+            let block = self
+                .data
+                .get(self.position..self.position + length)
+                .ok_or("GIF file ends unexpectedly")?;
+            data.extend_from_slice(block);
+            self.position += length;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pack `(code, size)` pairs least significant bit first.
+    fn pack(codes: &[(u16, u32)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let (mut bits, mut count) = (0u32, 0);
+        for &(code, size) in codes {
+            bits |= (code as u32) << count;
+            count += size;
+            while count >= 8 {
+                bytes.push(bits as u8);
+                bits >>= 8;
+                count -= 8;
+            }
+        }
+        if count > 0 {
+            bytes.push(bits as u8);
+        }
+        bytes
+    }
+
+    /// A 2x2 GIF with a two color table and the given LZW data.
+    fn gif(lzw: &[u8]) -> Vec<u8> {
+        let mut data = b"GIF89a".to_vec();
+        data.extend_from_slice(&[2, 0, 2, 0, 0x80, 0, 0]);
+        data.extend_from_slice(&[0, 0, 0, 255, 255, 255]);
+        data.extend_from_slice(&[0x2C, 0, 0, 0, 0, 2, 0, 2, 0, 0, 2]);
+        data.push(lzw.len() as u8);
+        data.extend_from_slice(lzw);
+        data.extend_from_slice(&[0, 0x3B]);
+        data
+    }
+
+    #[test]
+    fn decodes_a_small_image() {
+        // The code size grows to 4 bits once code 7 is added
+        let lzw = pack(&[(4, 3), (0, 3), (1, 3), (1, 3), (0, 4), (5, 4)]);
+        let frames = decode_frames(&gif(&lzw)).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!((frames[0].width, frames[0].height), (2, 2));
+        assert_eq!(frames[0].pixels, [0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn clear_code_resets_the_code_size() {
+        let lzw = pack(&[(4, 3), (0, 3), (1, 3), (1, 3), (4, 4), (2, 3), (5, 3)]);
+        assert_eq!(decompress(&lzw, 2, 16).unwrap(), [0, 1, 1, 2]);
+    }
+
+    #[test]
+    fn code_not_yet_in_the_table() {
+        // Code 6 is the previous string plus its own first index
+        let lzw = pack(&[(4, 3), (1, 3), (6, 3), (5, 3)]);
+        assert_eq!(decompress(&lzw, 2, 16).unwrap(), [1, 1, 1]);
+    }
+
+    #[test]
+    fn truncated_data() {
+        // Data that ends without an end code gives the pixels decoded so far
+        let lzw = pack(&[(8, 4), (0, 4), (1, 4), (1, 4)]);
+        assert_eq!(decompress(&lzw, 3, 16).unwrap(), [0, 1, 1]);
+        let lzw = pack(&[(4, 3), (0, 3), (1, 3), (5, 3)]);
+        assert!(decode_frames(&gif(&lzw)).is_err());
+        assert!(decode_frames(&gif(&pack(&[(4, 3), (7, 3)]))).is_err());
+    }
+}
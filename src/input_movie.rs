@@ -0,0 +1,64 @@
+/// A key going down or up at the start of a frame.
+#[derive(Clone, Copy, Debug)]
+pub struct InputEvent {
+    pub frame: u64, // Frames since the machine was reset
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// Keypad input recorded since the last reset, which can be played back
+/// after the ROM is reloaded to get back to the same spot in a game.
+#[derive(Default)]
+pub struct InputMovie {
+    events: Vec<InputEvent>,
+    position: usize, // Next event to play back
+    replaying: bool,
+}
+
+impl InputMovie {
+    pub fn record(&mut self, frame: u64, key: u8, pressed: bool) {
+        // Live input takes over from the recording at the current frame
+        if self.replaying {
+            self.events.truncate(self.position);
+            self.replaying = false;
+        }
+        self.events.push(InputEvent {
+            frame,
+            key,
+            pressed,
+        });
+    }
+
+    /// Play the recording back from the start; returns false if it's empty.
+    pub fn rewind(&mut self) -> bool {
+        self.position = 0;
+        self.replaying = !self.events.is_empty();
+        self.replaying
+    }
+
+    /// Forget everything recorded, for a reset that starts over.
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.position = 0;
+        self.replaying = false;
+    }
+
+    /// Events to apply before running `frame`, while playing back.
+    pub fn due(&mut self, frame: u64) -> &[InputEvent] {
+        if !self.replaying {
+            return &[];
+        }
+        let start = self.position;
+        while self
+            .events
+            .get(self.position)
+            .is_some_and(|event| event.frame <= frame)
+        {
+            self.position += 1;
+        }
+        if self.position == self.events.len() {
+            self.replaying = false;
+        }
+        &self.events[start..self.position]
+    }
+}
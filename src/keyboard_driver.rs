@@ -1,9 +1,89 @@
+use std::collections::HashMap;
+use std::fs;
+
 use sdl2::keyboard::Keycode;
 
-pub struct KeyboardDriver;
+use crate::KEYPAD_SIZE;
+use crate::rom_database::GameKeys;
+
+/// Emulator controls bound to keys outside the keypad.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    NextPalette,
+    NextFilter,
+    ToggleFullscreen,
+    Faster,
+    Slower,
+    TogglePause,
+    Turbo, // Held down
+    AdvanceFrame,
+    SaveState,
+    LoadState,
+    Reset,
+    HardReset,
+}
+
+pub struct KeyboardDriver {
+    keymap: HashMap<Keycode, usize>, // Keys added or remapped by a keymap file
+}
 
 impl KeyboardDriver {
-    pub fn to_chip8_key(keycode: Keycode) -> Option<usize> {
+    /// Create the driver, applying a keymap file of `key = digit` lines if given.
+    ///
+    /// Keys use SDL key names (`Up`, `Space`, `W`, ...), digits are hex keypad keys.
+    /// A game's direction and action keys go on the arrow keys, Space and Enter,
+    /// unless the keymap file binds them itself.
+    pub fn new(keymap_file: Option<&str>, game_keys: GameKeys) -> Result<KeyboardDriver, String> {
+        let mut keymap = HashMap::new();
+
+        let game_bindings = [
+            (Keycode::Up, game_keys.up),
+            (Keycode::Down, game_keys.down),
+            (Keycode::Left, game_keys.left),
+            (Keycode::Right, game_keys.right),
+            (Keycode::Space, game_keys.a),
+            (Keycode::Return, game_keys.b),
+        ];
+        for (keycode, key) in game_bindings {
+            if let Some(key) = key {
+                keymap.insert(keycode, key as usize);
+            }
+        }
+
+        if let Some(path) = keymap_file {
+            let contents = fs::read_to_string(path)
+                .map_err(|e| format!("Could not read keymap {}: {}", path, e))?;
+
+            for (line_number, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                let location = format!("{}:{}", path, line_number + 1);
+                let (name, value) = line
+                    .split_once('=')
+                    .map(|(name, value)| (name.trim(), value.trim()))
+                    .ok_or(format!("{}: expected `key = digit`", location))?;
+                let keycode = Keycode::from_name(name)
+                    .ok_or(format!("{}: unknown key {}", location, name))?;
+                let key = usize::from_str_radix(value, 16)
+                    .ok()
+                    .filter(|&key| key < KEYPAD_SIZE)
+                    .ok_or(format!("{}: invalid keypad digit {}", location, value))?;
+
+                keymap.insert(keycode, key);
+            }
+        }
+
+        Ok(KeyboardDriver { keymap })
+    }
+
+    pub fn to_chip8_key(&self, keycode: Keycode) -> Option<usize> {
+        if let Some(&key) = self.keymap.get(&keycode) {
+            return Some(key);
+        }
+
         match keycode {
             Keycode::Num1 => Some(0x1),
             Keycode::Num2 => Some(0x2),
@@ -24,4 +104,22 @@ impl KeyboardDriver {
             _ => None,
         }
     }
+
+    pub fn to_hotkey(keycode: Keycode) -> Option<Hotkey> {
+        match keycode {
+            Keycode::F2 => Some(Hotkey::NextPalette),
+            Keycode::F3 => Some(Hotkey::NextFilter),
+            Keycode::F11 => Some(Hotkey::ToggleFullscreen),
+            Keycode::Equals | Keycode::KpPlus => Some(Hotkey::Faster),
+            Keycode::Minus | Keycode::KpMinus => Some(Hotkey::Slower),
+            Keycode::P => Some(Hotkey::TogglePause),
+            Keycode::Tab => Some(Hotkey::Turbo),
+            Keycode::N => Some(Hotkey::AdvanceFrame),
+            Keycode::F5 => Some(Hotkey::SaveState),
+            Keycode::F9 => Some(Hotkey::LoadState),
+            Keycode::F6 => Some(Hotkey::Reset),
+            Keycode::F7 => Some(Hotkey::HardReset),
+            _ => None,
+        }
+    }
 }
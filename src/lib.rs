@@ -0,0 +1,37 @@
+//! CHIP-8 emulator core, SDL front end drivers and ROM tooling.
+
+extern crate rand;
+extern crate sdl2;
+
+pub mod call_graph;
+pub mod chip8;
+pub mod cli;
+pub mod constants;
+pub mod controller_driver;
+pub mod coverage;
+pub mod data_dir;
+pub mod detect;
+pub mod disassembler;
+pub mod display_driver;
+pub mod display_filter;
+pub mod fontset;
+pub mod gif;
+pub mod input_movie;
+pub mod keyboard_driver;
+pub mod memory_access;
+pub mod memory_layout;
+pub mod octo_assembler;
+pub mod octo_cartridge;
+pub mod palette;
+pub mod platform;
+pub mod profiler;
+pub mod quirks;
+pub mod rom_database;
+pub mod rom_watcher;
+pub mod speed_control;
+pub mod symbols;
+pub mod trace;
+pub mod trace_diff;
+pub mod user_flags;
+
+pub use constants::*;
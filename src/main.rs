@@ -1,103 +1,813 @@
-extern crate rand;
-extern crate sdl2;
+use chip8_emulator::call_graph::CallGraph;
+use chip8_emulator::chip8::{Chip8, Reset};
+use chip8_emulator::cli::{self, Command, Options};
+use chip8_emulator::controller_driver::{ControllerDriver, ControllerMapping};
+use chip8_emulator::coverage::Coverage;
+use chip8_emulator::detect::{Detection, detect_platform};
+use chip8_emulator::display_driver::DisplayDriver;
+use chip8_emulator::fontset::Fontset;
+use chip8_emulator::input_movie::InputMovie;
+use chip8_emulator::keyboard_driver::{Hotkey, KeyboardDriver};
+use chip8_emulator::memory_access::MemoryAccess;
+use chip8_emulator::memory_layout::MemoryLayout;
+use chip8_emulator::octo_cartridge::{self, Cartridge};
+use chip8_emulator::palette::Palette;
+use chip8_emulator::platform::Platform;
+use chip8_emulator::profiler::Profiler;
+use chip8_emulator::quirks::Quirks;
+use chip8_emulator::rom_database::{self, GameKeys, RomDatabase, RomInfo};
+use chip8_emulator::rom_watcher::RomWatcher;
+use chip8_emulator::speed_control::SpeedControl;
+use chip8_emulator::symbols::Symbols;
+use chip8_emulator::trace::{self, Tracer};
+use chip8_emulator::trace_diff::{self, Comparison};
+use chip8_emulator::user_flags::UserFlagFile;
+use chip8_emulator::{FRAME_HZ, INSTRUCTIONS_PER_FRAME, MAX_CATCHUP_FRAMES};
 
-mod chip8;
-mod constants;
-mod display_driver;
-mod keyboard_driver;
-
-pub use constants::*;
-
-use chip8::Chip8;
-use display_driver::DisplayDriver;
+use sdl2::event::WindowEvent;
 
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
 struct Rom {
-    rom: [u8; 3584], // 4096 - 512 = 3584 (512 bytes reserved for interpreter)
-    size: usize,
+    data: Vec<u8>, // The whole program, however large; capacity is checked on load
+    cartridge: Option<RomInfo>, // Settings embedded in an Octo cartridge
 }
 
 impl Rom {
+    /// Read a raw ROM, or unpack the program from an Octo cartridge GIF.
     fn new(filename: &str) -> Result<Rom, String> {
-        let mut f = File::open(filename).map_err(|e| e.to_string())?;
-        let mut buffer = [0u8; 3584];
+        let data =
+            fs::read(filename).map_err(|e| format!("Could not read ROM {}: {}", filename, e))?;
 
-        let rom_size = f.read(&mut buffer).map_err(|e| e.to_string())?;
+        let (program, cartridge) = if octo_cartridge::is_cartridge(&data) {
+            let name = Path::new(filename)
+                .file_stem()
+                .map_or(filename.into(), |stem| stem.to_string_lossy());
+            let cartridge = Cartridge::parse(&data, &name)
+                .map_err(|e| format!("Could not load cartridge {}: {}", filename, e))?;
+            (cartridge.program, Some(cartridge.info))
+        } else {
+            (data, None)
+        };
 
         Ok(Rom {
-            rom: buffer,
-            size: rom_size,
+            data: program,
+            cartridge,
         })
     }
 }
 
-fn main() {
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let options = match Options::parse(&args) {
+        Ok(Command::Run(options)) => *options,
+        Ok(Command::RomInfo { rom, database }) => {
+            return match rom_info(&rom, database.as_deref()) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        Ok(Command::TraceDiff {
+            traces,
+            comparison,
+            context,
+            symbols,
+        }) => {
+            return match trace_diff(&traces.0, &traces.1, &comparison, context, &symbols) {
+                Ok(true) => ExitCode::SUCCESS,
+                Ok(false) => ExitCode::from(1),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    ExitCode::from(2)
+                }
+            };
+        }
+        Ok(Command::CoverageReport {
+            rom,
+            coverage,
+            symbols,
+        }) => {
+            return match coverage_report(&rom, &coverage, &symbols) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("error: {}\nRun with --help for usage.", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Palettes from files: the ROM's own, and the user's default for all ROMs.
+struct PaletteFiles {
+    rom: Option<Palette>,
+    user_default: Option<Palette>,
+}
+
+/// Settings for a run: command-line options, else the ROM database, else defaults.
+struct Config {
+    platform: Platform,
+    quirks: Quirks,
+    layout: MemoryLayout,
+    fontset: Fontset,
+    speed: u32,
+    palette: Palette,
+    keys: GameKeys,
+    title: Option<String>,
+}
+
+impl Config {
+    fn new(
+        options: &Options,
+        info: Option<&RomInfo>,
+        detection: &Detection,
+        palette_files: PaletteFiles,
+    ) -> Config {
+        let platform = options
+            .platform
+            .or(info.and_then(|info| info.platform))
+            .unwrap_or(detection.platform);
+
+        let fontset = options.font.clone().unwrap_or(platform.fontset());
+
+        // An explicit platform brings its own quirks rather than the database's
+        let database_quirks = info
+            .and_then(|info| info.quirks)
+            .filter(|_| options.platform.is_none());
+
+        Config {
+            platform,
+            quirks: options
+                .quirks
+                .or(database_quirks)
+                .unwrap_or(platform.quirks()),
+            layout: options.layout.unwrap_or(platform.memory_layout()),
+            fontset: Fontset {
+                big: options.big_font.clone().unwrap_or(fontset.big),
+                ..fontset
+            },
+            speed: options
+                .speed
+                .or(info.and_then(|info| info.speed))
+                .unwrap_or(INSTRUCTIONS_PER_FRAME),
+            palette: options
+                .palette
+                .clone()
+                .or(palette_files.rom)
+                .or(info.and_then(|info| info.palette.clone()))
+                .or(palette_files.user_default)
+                .unwrap_or_default(),
+            keys: info.map(|info| info.keys).unwrap_or_default(),
+            title: info.map(RomInfo::label),
+        }
+    }
+}
+
+fn run(mut options: Options) -> Result<(), String> {
     println!("Welcome, CHIP-8 Emulator starting...");
 
-    let args: Vec<String> = env::args().collect();
-    let rom_name = &args[1];
-    let sdl2_context = sdl2::init().expect("Failed to initialize SDL2");
+    let (mut cpu, config, flag_file) = start(&options, &options.rom)?;
+
+    if let Some(path) = &options.state {
+        let state = fs::read(path).map_err(|e| format!("Could not read state {}: {}", path, e))?;
+        cpu.load_state(&state)?;
+    }
+
+    let symbols = std::mem::take(&mut options.symbols);
+    if let Some(path) = &options.trace {
+        let filter = options.trace_filter.clone();
+        let tracer = Tracer::create(path, options.trace_format, filter, symbols.clone())?;
+        cpu.set_tracer(Some(tracer));
+    }
+    let reports = Reports {
+        symbols,
+        profile: options.profile,
+        call_graph: options.call_graph.clone(),
+        chrome_trace: options.chrome_trace.clone(),
+        heatmap: options.heatmap.clone(),
+        coverage: options.coverage.clone(),
+    };
+    reports.start(&mut cpu);
+
+    let result = if options.headless {
+        run_headless(&mut cpu, &options, config.speed, &flag_file);
+        Ok(())
+    } else {
+        run_window(&mut cpu, options, config, flag_file, &reports)
+    };
+
+    if let Some(tracer) = cpu.take_tracer() {
+        tracer.finish()?;
+    }
+    reports.finish(&mut cpu)?;
+    result
+}
+
+/// The profile, call graph, memory heatmap and coverage asked for, collected
+/// per ROM.
+struct Reports {
+    symbols: Symbols,
+    profile: bool,
+    call_graph: Option<String>,
+    chrome_trace: Option<String>,
+    heatmap: Option<String>,
+    coverage: Option<String>,
+}
+
+impl Reports {
+    /// Attach fresh collectors to a machine.
+    fn start(&self, cpu: &mut Chip8) {
+        if self.profile {
+            cpu.set_profiler(Some(Profiler::new()));
+        }
+        if self.call_graph.is_some() || self.chrome_trace.is_some() {
+            cpu.set_call_graph(Some(CallGraph::new()));
+        }
+        if self.heatmap.is_some() {
+            cpu.set_memory_access(Some(MemoryAccess::new(cpu.memory().len())));
+        }
+        if self.coverage.is_some() {
+            start_coverage(cpu);
+        }
+    }
+
+    /// Print and write the results for the program the machine ran, taking
+    /// its collectors.
+    fn finish(&self, cpu: &mut Chip8) -> Result<(), String> {
+        if let Some(profiler) = cpu.take_profiler() {
+            println!("{}", profiler.report(cpu.memory(), &self.symbols));
+        }
+        if let Some(call_graph) = cpu.take_call_graph() {
+            write_call_graph(
+                &call_graph,
+                (self.call_graph.clone(), self.chrome_trace.clone()),
+                cpu.layout().entry_point,
+                &self.symbols,
+            )?;
+        }
+        if let Some(memory_access) = cpu.take_memory_access()
+            && let Some(path) = &self.heatmap
+        {
+            println!("{}", memory_access.report(cpu.memory(), &self.symbols));
+            memory_access.save_heatmap(path)?;
+        }
+        if let Some(path) = &self.coverage {
+            save_coverage(cpu, path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Load a ROM and pick its settings, returning a machine ready to run it.
+fn start(options: &Options, path: &str) -> Result<(Chip8, Config, UserFlagFile), String> {
+    let rom = Rom::new(path)?;
+    println!("Loaded ROM of size: {} bytes", rom.data.len());
+
+    let rom_bytes = &rom.data[..];
+    let is_cartridge = rom.cartridge.is_some();
+    let info = if rom.cartridge.is_some() {
+        rom.cartridge
+    } else if options.no_database {
+        None
+    } else {
+        load_database(options.database.as_deref())?.lookup(rom_bytes)
+    };
+    let detection = detect_platform(rom_bytes);
+    match &info {
+        Some(_) if is_cartridge => println!("Using settings from the Octo cartridge"),
+        Some(info) => println!("Found in ROM database: {}", info.label()),
+        None if options.platform.is_none() => println!(
+            "Detected platform {} ({} confidence: {})",
+            detection.platform.name(),
+            detection.confidence,
+            detection.evidence.join(", ")
+        ),
+        None => {}
+    }
+    let palette_files = PaletteFiles {
+        rom: Palette::for_rom(path)?,
+        user_default: Palette::user_default()?,
+    };
+    let config = Config::new(options, info.as_ref(), &detection, palette_files);
+
+    let seed = options.seed.unwrap_or_else(rand::random);
+    println!(
+        "Platform: {}, random seed: {}",
+        config.platform.name(),
+        seed
+    );
+
+    let flag_file = UserFlagFile::for_rom(options.flags_dir.as_deref(), rom_bytes);
+    let cpu = boot(rom_bytes, &config, seed, &flag_file)?;
+    Ok((cpu, config, flag_file))
+}
+
+/// A fresh machine with the ROM loaded.
+fn boot(rom: &[u8], config: &Config, seed: u64, flags: &UserFlagFile) -> Result<Chip8, String> {
+    let mut cpu = Chip8::new(config.quirks, config.layout, &config.fontset, seed)?;
+    if let Some(warning) = config.platform.size_warning(rom.len(), &config.layout) {
+        println!("Warning: {}", warning);
+    }
+    cpu.load_rom(rom).map_err(|e| match config.platform {
+        Platform::XoChip => e,
+        _ => format!("{}; try --platform xochip for 64 KiB of memory", e),
+    })?;
+    cpu.set_user_flags(flags.load(config.platform.user_flags()));
+    Ok(cpu)
+}
+
+/// Write the RPL user flags back if the program changed them.
+fn save_user_flags(cpu: &mut Chip8, flags: &UserFlagFile) {
+    if cpu.take_user_flags_changed()
+        && let Err(e) = flags.save(cpu.user_flags())
+    {
+        println!("Could not save user flags: {}", e);
+    }
+}
+
+/// Press or release a key, recording it when input is kept for replay.
+fn set_key(cpu: &mut Chip8, movie: &mut Option<InputMovie>, frame: u64, key: u8, pressed: bool) {
+    cpu.set_key(key, pressed);
+    if let Some(movie) = movie {
+        movie.record(frame, key, pressed);
+    }
+}
+
+/// The database given with `--database`, else the installed one, else the
+/// bundled one.
+fn load_database(path: Option<&str>) -> Result<RomDatabase, String> {
+    match path {
+        Some(path) => RomDatabase::load(path),
+        None => match RomDatabase::installed()? {
+            Some(database) => Ok(database),
+            None => RomDatabase::bundled(),
+        },
+    }
+}
+
+/// Print what is known about a ROM without running it.
+fn rom_info(path: &str, database: Option<&str>) -> Result<(), String> {
+    let loaded = Rom::new(path)?;
+    let rom = &loaded.data[..];
+
+    println!("File:       {}", path);
+    println!("Size:       {} bytes", rom.len());
+    println!("SHA-1:      {}", rom_database::rom_hash(rom));
+    if let Some(info) = &loaded.cartridge {
+        println!("Cartridge:  Octo cartridge");
+        if let Some(speed) = info.speed {
+            println!("            {} instructions/frame", speed);
+        }
+    }
+
+    match load_database(database)?.lookup(rom) {
+        Some(info) => {
+            println!("Database:   {}", info.label());
+            if let Some(platform) = info.platform {
+                println!("            platform {}", platform.name());
+            }
+            if let Some(speed) = info.speed {
+                println!("            {} instructions/frame", speed);
+            }
+        }
+        None => println!("Database:   not found"),
+    }
+
+    let detection = detect_platform(rom);
+    println!(
+        "Detected:   {} ({} confidence)",
+        detection.platform.name(),
+        detection.confidence
+    );
+    println!("Evidence:   {}", detection.evidence.join(", "));
+    println!(
+        "Quirks:     {}",
+        detection.platform.quirks().enabled().join(", ")
+    );
+
+    Ok(())
+}
+
+/// Print the per-subroutine report and write the requested call graph files.
+fn write_call_graph(
+    call_graph: &CallGraph,
+    (folded, chrome_trace): (Option<String>, Option<String>),
+    entry_point: u16,
+    symbols: &Symbols,
+) -> Result<(), String> {
+    println!("{}", call_graph.report(symbols));
+
+    if let Some(path) = folded {
+        fs::write(&path, call_graph.folded(entry_point, symbols))
+            .map_err(|e| format!("Could not write call graph {}: {}", path, e))?;
+    }
+    if let Some(path) = chrome_trace {
+        fs::write(&path, call_graph.chrome_trace(entry_point, symbols))
+            .map_err(|e| format!("Could not write Chrome trace {}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+/// Record coverage for the program the machine is running.
+fn start_coverage(cpu: &mut Chip8) {
+    let coverage = Coverage::new(cpu.rom(), cpu.layout().load_address);
+    cpu.set_coverage(Some(coverage));
+}
+
+/// Add the coverage collected so far to the file and print a summary. A file
+/// holding coverage of a different ROM is replaced.
+fn save_coverage(cpu: &mut Chip8, path: &str) -> Result<(), String> {
+    let Some(mut coverage) = cpu.take_coverage() else {
+        return Ok(());
+    };
+    if Path::new(path).exists() {
+        let previous = Coverage::load(path)?;
+        if coverage.merge(&previous).is_err() {
+            println!("Replacing coverage of a different ROM in {}", path);
+        }
+    }
+    coverage.save(path)?;
+    println!("{}", coverage.summary(cpu.rom()));
+    Ok(())
+}
+
+/// Print the annotated listing of a ROM for the merged coverage files.
+fn coverage_report(path: &str, files: &[String], symbols: &Symbols) -> Result<(), String> {
+    let rom = Rom::new(path)?.data;
+    let mut coverage = Coverage::load(&files[0])?;
+    for file in &files[1..] {
+        coverage
+            .merge(&Coverage::load(file)?)
+            .map_err(|e| format!("{}: {}", file, e))?;
+    }
+    if !coverage.is_for(&rom) {
+        return Err(format!("{} is coverage of a different ROM", files[0]));
+    }
+    println!("{}", coverage.listing(&rom, symbols));
+    Ok(())
+}
+
+/// Compare two traces, returning whether they match.
+fn trace_diff(
+    first: &str,
+    second: &str,
+    comparison: &Comparison,
+    context: usize,
+    symbols: &Symbols,
+) -> Result<bool, String> {
+    let a = trace::read_trace(first)?;
+    let b = trace::read_trace(second)?;
+
+    match trace_diff::first_divergence(&a, &b, comparison) {
+        Some(divergence) => {
+            let names = (first, second);
+            println!(
+                "{}",
+                trace_diff::report(&a, &b, names, &divergence, context, symbols)
+            );
+            Ok(false)
+        }
+        None if a.len() == b.len() => {
+            println!("Traces match ({} entries)", a.len());
+            Ok(true)
+        }
+        None => {
+            println!(
+                "Traces match from the first cycle both have ({} and {} entries)",
+                a.len(),
+                b.len()
+            );
+            Ok(true)
+        }
+    }
+}
+
+/// Run as fast as possible without SDL, then print the final screen.
+fn run_headless(cpu: &mut Chip8, options: &Options, speed: u32, flags: &UserFlagFile) {
+    let Some(frames) = options.frames else {
+        loop {
+            cpu.run_frame(speed);
+            save_user_flags(cpu, flags);
+        }
+    };
 
-    let rom = Rom::new(rom_name).expect("Failed to load ROM");
-    println!("Loaded ROM of size: {} bytes", rom.size);
-    let mut display_driver =
-        DisplayDriver::new(&sdl2_context).expect("Failed to initialize display driver");
-    let mut cpu = Chip8::new().expect("Failed to initialize CHIP-8");
+    for frame in 1..=frames {
+        let state = cpu.run_frame(speed);
+        if frame == frames {
+            for row in state.video {
+                let line: String = row
+                    .iter()
+                    .map(|&pixel| if pixel == 0 { '.' } else { '#' })
+                    .collect();
+                println!("{}", line);
+            }
+        }
+        save_user_flags(cpu, flags);
+    }
+}
 
-    cpu.load_rom(&rom.rom).expect("Could not load rom");
+fn run_window(
+    cpu: &mut Chip8,
+    mut options: Options,
+    mut config: Config,
+    mut flag_file: UserFlagFile,
+    reports: &Reports,
+) -> Result<(), String> {
+    let sdl2_context = sdl2::init().map_err(|e| format!("Failed to initialize SDL2: {}", e))?;
 
-    let mut event_pump = sdl2_context.event_pump().unwrap();
+    let mut state_path = options
+        .state
+        .clone()
+        .unwrap_or(format!("{}.state", options.rom));
+    let mut speed = SpeedControl::new(config.speed);
+    speed.paused = options.paused;
+    speed.game = config.title.clone();
 
-    let cycle_duration = std::time::Duration::from_nanos(1_000_000_000 / CPU_HZ as u64);
-    let mut last_cycle_time = std::time::Instant::now();
-    let mut cycles_since_timer_update = 0;
+    let mut display_driver = DisplayDriver::new(
+        &sdl2_context,
+        config.palette.clone(),
+        std::mem::take(&mut options.filter), // Only the display uses it
+        options.scale,
+        options.integer_scale,
+        options.vsync,
+    )
+    .map_err(|e| format!("Failed to initialize display: {}", e))?;
+    let mut keyboard_driver = KeyboardDriver::new(options.keymap.as_deref(), config.keys)?;
+    let controller_mapping = ControllerMapping::for_rom(&options.rom, config.keys)?;
+    let mut controller_driver = ControllerDriver::new(&sdl2_context, controller_mapping)
+        .map_err(|e| format!("Failed to initialize game controllers: {}", e))?;
+
+    let mut event_pump = sdl2_context.event_pump()?;
+
+    let frame_duration = Duration::from_secs(1) / FRAME_HZ;
+    let mut last_time = Instant::now();
+    let mut accumulator = Duration::ZERO; // Real time not yet emulated
+    let mut frames_run: u64 = 0;
+    let mut screen_dirty = true;
+    let mut watcher = options.watch.then(|| RomWatcher::new(&options.rom));
+    let mut movie = options.replay_input.then(InputMovie::default);
+    let mut movie_frame: u64 = 0; // Frames since the last reset
+    let _ = display_driver.set_title(&speed.title());
 
     loop {
         for event in event_pump.poll_iter() {
+            for (key, pressed) in controller_driver.handle_event(&event) {
+                set_key(cpu, &mut movie, movie_frame, key, pressed);
+            }
+
             match event {
-                sdl2::event::Event::Quit { .. } => return,
+                sdl2::event::Event::Quit { .. } => return Ok(()),
 
                 sdl2::event::Event::KeyDown {
                     keycode: Some(keycode),
+                    repeat,
                     ..
                 } => {
-                    if let Some(key) = keyboard_driver::KeyboardDriver::to_chip8_key(keycode) {
-                        cpu.set_key(key as u8, true);
+                    if let Some(key) = keyboard_driver.to_chip8_key(keycode) {
+                        set_key(cpu, &mut movie, movie_frame, key as u8, true);
                     }
+                    match KeyboardDriver::to_hotkey(keycode) {
+                        Some(Hotkey::NextPalette) => {
+                            let palette = display_driver.palette().next();
+                            println!("Palette: {}", palette.name());
+                            display_driver.set_palette(palette);
+                            screen_dirty = true;
+                        }
+                        Some(Hotkey::NextFilter) => {
+                            let filter = display_driver.filter_mut();
+                            filter.cycle_mode();
+                            println!("Display filter: {}", filter.name());
+                            screen_dirty = true;
+                        }
+                        Some(Hotkey::ToggleFullscreen) => {
+                            if let Err(e) = display_driver.toggle_fullscreen() {
+                                println!("Could not toggle fullscreen: {}", e);
+                            }
+                            screen_dirty = true;
+                        }
+                        Some(Hotkey::Faster) => speed.faster(),
+                        Some(Hotkey::Slower) => speed.slower(),
+                        Some(Hotkey::TogglePause) if !repeat => speed.toggle_pause(),
+                        Some(Hotkey::Turbo) => speed.turbo = true,
+                        Some(Hotkey::AdvanceFrame) => speed.advance_frame(),
+                        Some(Hotkey::SaveState) if !repeat => {
+                            match fs::write(&state_path, cpu.save_state()) {
+                                Ok(()) => println!("Saved state to {}", state_path),
+                                Err(e) => println!("Could not save state: {}", e),
+                            }
+                        }
+                        Some(Hotkey::Reset) if !repeat => {
+                            let keep_seed = options.keep_seed || options.seed.is_some();
+                            cpu.reset(Reset::Soft { keep_seed });
+                            println!("Reset, random seed: {}", cpu.seed());
+                            movie = options.replay_input.then(InputMovie::default);
+                            movie_frame = 0;
+                            screen_dirty = true;
+                        }
+                        Some(Hotkey::HardReset) if !repeat => {
+                            cpu.reset(Reset::Hard);
+                            println!("Hard reset, random seed: {}", cpu.seed());
+                            movie = options.replay_input.then(InputMovie::default);
+                            movie_frame = 0;
+                            screen_dirty = true;
+                        }
+                        Some(Hotkey::LoadState) if !repeat => {
+                            let loaded = fs::read(&state_path)
+                                .map_err(|e| e.to_string())
+                                .and_then(|state| cpu.load_state(&state));
+                            match loaded {
+                                Ok(()) => {
+                                    println!("Loaded state from {}", state_path);
+                                    // Input recorded so far no longer leads here
+                                    if let Some(movie) = &mut movie {
+                                        movie.clear();
+                                    }
+                                }
+                                Err(e) => println!("Could not load state: {}", e),
+                            }
+                            screen_dirty = true;
+                        }
+                        _ => {}
+                    }
+                    let _ = display_driver.set_title(&speed.title());
                 }
                 sdl2::event::Event::KeyUp {
                     keycode: Some(keycode),
                     ..
                 } => {
-                    if let Some(key) = keyboard_driver::KeyboardDriver::to_chip8_key(keycode) {
-                        cpu.set_key(key as u8, false);
+                    if let Some(key) = keyboard_driver.to_chip8_key(keycode) {
+                        set_key(cpu, &mut movie, movie_frame, key as u8, false);
+                    }
+                    if let Some(Hotkey::Turbo) = KeyboardDriver::to_hotkey(keycode) {
+                        speed.turbo = false;
+                        let _ = display_driver.set_title(&speed.title());
                     }
                 }
+                sdl2::event::Event::DropFile { filename, .. } => {
+                    let started = start(&options, &filename).and_then(|(cpu, config, flags)| {
+                        let keys = KeyboardDriver::new(options.keymap.as_deref(), config.keys)?;
+                        let mapping = ControllerMapping::for_rom(&filename, config.keys)?;
+                        Ok((cpu, config, flags, keys, mapping))
+                    });
+                    match started {
+                        Ok((mut new_cpu, new_config, flags, keys, mapping)) => {
+                            // The trace goes on; the other reports are per ROM
+                            new_cpu.set_tracer(cpu.take_tracer());
+                            if let Err(e) = reports.finish(cpu) {
+                                println!("Could not write reports: {}", e);
+                            }
+                            reports.start(&mut new_cpu);
+                            *cpu = new_cpu;
+                            config = new_config;
+                            flag_file = flags;
+                            keyboard_driver = keys;
+                            controller_driver.set_mapping(mapping);
+
+                            speed.instructions_per_frame = config.speed;
+                            speed.game = config.title.clone();
+                            display_driver.set_palette(config.palette.clone());
+                            if options.state.is_none() {
+                                state_path = format!("{}.state", filename);
+                            }
+                            if watcher.is_some() {
+                                watcher = Some(RomWatcher::new(&filename));
+                            }
+                            movie = options.replay_input.then(InputMovie::default);
+                            movie_frame = 0;
+                            options.rom = filename;
+                            let _ = display_driver.set_title(&speed.title());
+                        }
+                        // Keep running the current program
+                        Err(e) => println!("Could not open dropped file: {}", e),
+                    }
+                }
+                sdl2::event::Event::Window {
+                    win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed,
+                    ..
+                } => screen_dirty = true,
                 _ => {}
             }
         }
 
-        let state = cpu.cycle();
+        if watcher.as_mut().is_some_and(RomWatcher::poll) {
+            let seed = if options.keep_seed || options.seed.is_some() {
+                cpu.seed()
+            } else {
+                rand::random()
+            };
+            let reloaded = Rom::new(&options.rom).and_then(|rom| {
+                let flags = UserFlagFile::for_rom(options.flags_dir.as_deref(), &rom.data);
+                Ok((boot(&rom.data, &config, seed, &flags)?, flags))
+            });
+            match reloaded {
+                Ok((mut reloaded, flags)) => {
+                    reloaded.set_tracer(cpu.take_tracer());
+                    reloaded.set_profiler(cpu.take_profiler());
+                    reloaded.set_call_graph(cpu.take_call_graph());
+                    reloaded.set_memory_access(cpu.take_memory_access());
+                    if let Some(path) = &options.coverage {
+                        if let Err(e) = save_coverage(cpu, path) {
+                            println!("Could not save coverage: {}", e);
+                        }
+                        start_coverage(&mut reloaded);
+                    }
+                    *cpu = reloaded;
+                    flag_file = flags;
+                    movie_frame = 0;
+                    let replaying = movie.as_mut().is_some_and(InputMovie::rewind);
+                    println!(
+                        "Reloaded {}, random seed: {}{}",
+                        options.rom,
+                        seed,
+                        if replaying { ", replaying input" } else { "" }
+                    );
+                    screen_dirty = true;
+                }
+                // Keep running the old program; the next save will be picked up
+                Err(e) => println!("Could not reload ROM: {}", e),
+            }
+        }
+
+        let now = Instant::now();
+        accumulator += now - last_time;
+        last_time = now;
+
+        // Don't try to catch up on long stalls, like a window being dragged
+        accumulator = accumulator.min(frame_duration * MAX_CATCHUP_FRAMES);
 
-        if state.video_draw {
-            display_driver.draw_screen(state.video);
+        let frames_due = if speed.paused || speed.turbo {
+            accumulator = Duration::ZERO;
+            u32::from(std::mem::take(&mut speed.advance_requested))
+        } else {
+            let frames_due = (accumulator.as_nanos() / frame_duration.as_nanos()) as u32;
+            accumulator -= frame_duration * frames_due;
+            frames_due
+        };
+
+        // Turbo runs frames back to back until the next real frame is due
+        let turbo_deadline = (speed.turbo && !speed.paused).then(|| now + frame_duration);
+
+        let mut frame = 0;
+        while frame < frames_due || turbo_deadline.is_some() {
+            frame += 1;
+            frames_run += 1;
+            if let Some(movie) = &mut movie {
+                for event in movie.due(movie_frame) {
+                    cpu.set_key(event.key, event.pressed);
+                }
+            }
+            movie_frame += 1;
+            let state = cpu.run_frame(speed.instructions_per_frame);
+            screen_dirty |= state.video_draw;
+
+            let last_frame = match turbo_deadline {
+                Some(deadline) => Instant::now() >= deadline,
+                None => frame == frames_due,
+            };
+            if last_frame {
+                // Present at most once per frame, and keep presenting while pixels fade out
+                if screen_dirty || display_driver.is_animating() {
+                    display_driver.draw_screen(state.video)?;
+                    screen_dirty = false;
+                }
+                break;
+            }
         }
 
-        // Update timers at 60Hz
-        cycles_since_timer_update += 1;
-        if cycles_since_timer_update >= CYCLES_PER_TIMER_TICK {
-            cpu.update_timers();
-            cycles_since_timer_update = 0;
+        save_user_flags(cpu, &flag_file);
+
+        if options.frames.is_some_and(|frames| frames_run >= frames) {
+            return Ok(());
         }
 
-        // Maintain consistent timing
-        let elapsed = last_cycle_time.elapsed();
-        if elapsed < cycle_duration {
-            std::thread::sleep(cycle_duration - elapsed);
+        // Sleep until the next frame is due; oversleeping is made up by the accumulator
+        let elapsed = last_time.elapsed() + accumulator;
+        if elapsed < frame_duration {
+            std::thread::sleep(frame_duration - elapsed);
         }
-        last_cycle_time = std::time::Instant::now();
     }
 }
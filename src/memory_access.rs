@@ -0,0 +1,243 @@
+//! Reads, writes and executions per byte of memory, for telling code, data
+//! and scratch space apart and for catching self-modifying code.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::disassembler;
+use crate::symbols::Symbols;
+
+// Cells per side of the heatmap, and pixels per side of a cell in the image
+const HEATMAP_CELLS: usize = 64;
+const CELL_PIXELS: usize = 8;
+// Brightest color channel for ordinary cells; self-modified cells are white
+const MAX_CHANNEL: f64 = 200.0;
+
+/// Per-byte access counts, collected while a `Chip8` runs.
+pub struct MemoryAccess {
+    reads: Vec<u64>,  // Bytes read by Dxyn and Fx65
+    writes: Vec<u64>, // Bytes written by Fx33 and Fx55
+    executions: Vec<u64>,
+    stored_by: Vec<Option<u16>>, // Address of the store that last wrote each byte
+    self_modified: BTreeMap<(u16, u16), u64>, // Executions of stored bytes: (byte, store)
+}
+
+impl MemoryAccess {
+    pub fn new(memory_size: usize) -> MemoryAccess {
+        MemoryAccess {
+            reads: vec![0; memory_size],
+            writes: vec![0; memory_size],
+            executions: vec![0; memory_size],
+            stored_by: vec![None; memory_size],
+            self_modified: BTreeMap::new(),
+        }
+    }
+
+    /// Count the memory an instruction at `pc` touched, with `i` as it was
+    /// before the instruction ran.
+    pub fn record(&mut self, pc: u16, opcode: u16, i: u16) {
+        for address in self.span(pc, 2) {
+            self.executions[address] += 1;
+            if let Some(store) = self.stored_by[address] {
+                *self
+                    .self_modified
+                    .entry((address as u16, store))
+                    .or_default() += 1;
+            }
+        }
+
+        let x = ((opcode >> 8) & 0xF) as usize;
+        match opcode & 0xF0FF {
+            0xF033 => self.write(pc, i, 3),
+            0xF055 => self.write(pc, i, x + 1),
+            0xF065 => self.read(i, x + 1),
+            _ if opcode & 0xF000 == 0xD000 => self.read(i, sprite_length(opcode)),
+            _ => {}
+        }
+    }
+
+    /// Forget which stores wrote memory, as a reset reloads it.
+    pub fn reset(&mut self) {
+        self.stored_by.fill(None);
+    }
+
+    fn read(&mut self, i: u16, length: usize) {
+        for address in self.span(i, length) {
+            self.reads[address] += 1;
+        }
+    }
+
+    fn write(&mut self, pc: u16, i: u16, length: usize) {
+        for address in self.span(i, length) {
+            self.writes[address] += 1;
+            self.stored_by[address] = Some(pc);
+        }
+    }
+
+    /// Addresses from `start` on, wrapping around at the end of memory.
+    fn span(&self, start: u16, length: usize) -> impl Iterator<Item = usize> + use<> {
+        let size = self.executions.len();
+        (0..length).map(move |offset| (start as usize + offset) % size)
+    }
+
+    /// What a byte was used for: executed bytes are code, bytes the program
+    /// wrote are scratch and bytes it only read are data.
+    fn region(&self, address: usize) -> Option<&'static str> {
+        if self.executions[address] > 0 {
+            Some("code")
+        } else if self.writes[address] > 0 {
+            Some("scratch")
+        } else if self.reads[address] > 0 {
+            Some("data")
+        } else {
+            None
+        }
+    }
+
+    /// Memory regions by use and the stored bytes that were later executed.
+    /// `memory` is used to disassemble the stores, `symbols` to name them.
+    pub fn report(&self, memory: &[u8], symbols: &Symbols) -> String {
+        let mut regions: Vec<(usize, usize, &str)> = Vec::new();
+        for address in 0..self.executions.len() {
+            let Some(region) = self.region(address) else {
+                continue;
+            };
+            match regions.last_mut() {
+                Some((_, end, last)) if *end + 1 == address && *last == region => *end = address,
+                _ => regions.push((address, address, region)),
+            }
+        }
+
+        let bytes = |name| {
+            let sizes = regions.iter().filter(|&&(_, _, region)| region == name);
+            sizes.map(|&(start, end, _)| end - start + 1).sum::<usize>()
+        };
+        let mut lines = vec![format!(
+            "Memory use: {} bytes of code, {} of data, {} of scratch",
+            bytes("code"),
+            bytes("data"),
+            bytes("scratch")
+        )];
+        for (start, end, region) in regions {
+            lines.push(format!("  {:04X}-{:04X}  {}", start, end, region));
+        }
+
+        lines.push(String::new());
+        lines.push("Self-modifying code (stored by Fx33/Fx55, then executed):".to_string());
+        for (&(address, store), &executions) in &self.self_modified {
+            let byte = |offset: usize| memory[(store as usize + offset) % memory.len()] as u16;
+            lines.push(format!(
+                "  {}  written at {} ({}), executed {} times",
+                symbols.name(address),
+                symbols.name(store),
+                disassembler::mnemonic((byte(0) << 8) | byte(1), symbols),
+                executions
+            ));
+        }
+        if self.self_modified.is_empty() {
+            lines.push("  none".to_string());
+        }
+
+        lines.join("\n")
+    }
+
+    /// Write memory as a 64x64 grid of cells to a BMP image, one byte per
+    /// cell for 4 KiB of memory and more for larger memories. Reads show in
+    /// green, writes in red and executions in blue, brighter the more often
+    /// they happened; self-modified bytes are white.
+    pub fn save_heatmap(&self, path: &str) -> Result<(), String> {
+        let cells = HEATMAP_CELLS * HEATMAP_CELLS;
+        let bytes_per_cell = self.executions.len().div_ceil(cells);
+        let self_modified: Vec<usize> = self
+            .self_modified
+            .keys()
+            .map(|&(address, _)| address as usize / bytes_per_cell)
+            .collect();
+
+        let totals = |counts: &[u64]| -> Vec<u64> {
+            counts
+                .chunks(bytes_per_cell)
+                .map(|chunk| chunk.iter().sum())
+                .chain(std::iter::repeat(0))
+                .take(cells)
+                .collect()
+        };
+        let channels = [
+            totals(&self.executions),
+            totals(&self.reads),
+            totals(&self.writes),
+        ];
+        let maxima: Vec<u64> = channels
+            .iter()
+            .map(|counts| counts.iter().copied().max().unwrap_or(0))
+            .collect();
+
+        let colors: Vec<[u8; 3]> = (0..cells)
+            .map(|cell| {
+                if self_modified.contains(&cell) {
+                    return [255; 3];
+                }
+                // Log scale, so rarely touched bytes still show up
+                let mut color = [0; 3];
+                for (channel, counts) in channels.iter().enumerate() {
+                    if counts[cell] > 0 {
+                        let level =
+                            (counts[cell] as f64).ln_1p() / (maxima[channel] as f64).ln_1p();
+                        color[channel] = (MAX_CHANNEL * (0.25 + 0.75 * level)) as u8;
+                    }
+                }
+                color // Blue, green, red as BMP stores them
+            })
+            .collect();
+
+        let side = HEATMAP_CELLS * CELL_PIXELS;
+        let mut pixels = Vec::with_capacity(side * side * 3);
+        // BMP rows go from the bottom up
+        for y in (0..side).rev() {
+            for x in 0..side {
+                pixels
+                    .extend_from_slice(&colors[y / CELL_PIXELS * HEATMAP_CELLS + x / CELL_PIXELS]);
+            }
+        }
+
+        fs::write(path, bmp(side, &pixels))
+            .map_err(|e| format!("Could not write heatmap {}: {}", path, e))
+    }
+}
+
+/// Bytes of sprite data a Dxyn reads: `n` rows, or a 16x16 sprite of 32
+/// bytes for Dxy0 as on SUPER-CHIP and XO-CHIP. Only one bit plane is drawn,
+/// so there are no further planes to read.
+fn sprite_length(opcode: u16) -> usize {
+    match (opcode & 0xF) as usize {
+        0 => 32,
+        rows => rows,
+    }
+}
+
+/// A 24-bit BMP file of a square image whose rows need no padding.
+fn bmp(side: usize, pixels: &[u8]) -> Vec<u8> {
+    const HEADER_SIZE: u32 = 14 + 40;
+    const PIXELS_PER_METER: u32 = 2835; // 72 DPI
+
+    let mut file = Vec::with_capacity(HEADER_SIZE as usize + pixels.len());
+    file.extend_from_slice(b"BM");
+    file.extend_from_slice(&(HEADER_SIZE + pixels.len() as u32).to_le_bytes());
+    file.extend_from_slice(&0u32.to_le_bytes());
+    file.extend_from_slice(&HEADER_SIZE.to_le_bytes());
+
+    file.extend_from_slice(&40u32.to_le_bytes());
+    file.extend_from_slice(&(side as u32).to_le_bytes());
+    file.extend_from_slice(&(side as u32).to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes()); // Planes
+    file.extend_from_slice(&24u16.to_le_bytes()); // Bits per pixel
+    file.extend_from_slice(&0u32.to_le_bytes()); // No compression
+    file.extend_from_slice(&(pixels.len() as u32).to_le_bytes());
+    file.extend_from_slice(&PIXELS_PER_METER.to_le_bytes());
+    file.extend_from_slice(&PIXELS_PER_METER.to_le_bytes());
+    file.extend_from_slice(&0u32.to_le_bytes()); // Colors in the palette
+    file.extend_from_slice(&0u32.to_le_bytes()); // Important colors
+
+    file.extend_from_slice(pixels);
+    file
+}
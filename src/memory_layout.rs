@@ -0,0 +1,123 @@
+/// Where a machine keeps its program and fonts, and how much memory it has.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MemoryLayout {
+    pub entry_point: u16,   // PC after reset
+    pub load_address: u16,  // Where the ROM is copied to
+    pub font_base: u16,     // 4x5 hex digit sprites used by Fx29
+    pub big_font_base: u16, // 8x10 digit sprites used by Fx30
+    pub memory_size: usize, // Bytes of RAM; addresses wrap around at this size
+}
+
+impl MemoryLayout {
+    /// COSMAC VIP and most later interpreters.
+    pub const CHIP8: MemoryLayout = MemoryLayout {
+        entry_point: 0x200,
+        load_address: 0x200,
+        font_base: 0x000,
+        big_font_base: 0x050,
+        memory_size: 4096,
+    };
+
+    /// ETI-660, whose interpreter occupies memory up to 0x600.
+    pub const ETI660: MemoryLayout = MemoryLayout {
+        entry_point: 0x600,
+        load_address: 0x600,
+        ..MemoryLayout::CHIP8
+    };
+
+    /// XO-CHIP with its 64 KiB of memory.
+    pub const XOCHIP: MemoryLayout = MemoryLayout {
+        memory_size: 0x10000,
+        ..MemoryLayout::CHIP8
+    };
+
+    pub fn named(name: &str) -> Option<MemoryLayout> {
+        match name {
+            "chip8" | "vip" | "schip" | "superchip" => Some(MemoryLayout::CHIP8),
+            "eti660" => Some(MemoryLayout::ETI660),
+            "xochip" => Some(MemoryLayout::XOCHIP),
+            _ => None,
+        }
+    }
+
+    /// Parse a layout name followed by optional overrides, e.g.
+    /// `chip8,entry=0x300,load=0x300` or `eti660,memory=8192`.
+    pub fn parse(spec: &str) -> Result<MemoryLayout, String> {
+        let mut parts = spec.split(',').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let mut layout = MemoryLayout::named(name).ok_or(format!(
+            "Unknown memory layout {} (chip8, eti660, xochip)",
+            name
+        ))?;
+
+        for part in parts {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or(format!("Memory layout setting {} must be key=value", part))?;
+            let value =
+                parse_address(value).ok_or(format!("Invalid address {} for {}", value, key))?;
+            let address = u16::try_from(value).map_err(|_| format!("{} is out of range", part));
+
+            match key {
+                "entry" => layout.entry_point = address?,
+                "load" => layout.load_address = address?,
+                "font" => layout.font_base = address?,
+                "bigfont" => layout.big_font_base = address?,
+                "memory" => layout.memory_size = value,
+                _ => {
+                    return Err(format!(
+                        "Unknown memory layout setting {} (entry, load, font, bigfont, memory)",
+                        key
+                    ));
+                }
+            }
+        }
+
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    /// Check that everything fits in memory.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0x200..=0x10000).contains(&self.memory_size) {
+            return Err(format!(
+                "Memory size {} must be between 512 and 65536 bytes",
+                self.memory_size
+            ));
+        }
+
+        let addresses = [
+            ("entry point", self.entry_point),
+            ("load address", self.load_address),
+            ("font", self.font_base),
+            ("big font", self.big_font_base),
+        ];
+        for (name, address) in addresses {
+            if address as usize >= self.memory_size {
+                return Err(format!(
+                    "The {} at {:#05x} is outside of {} bytes of memory",
+                    name, address, self.memory_size
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Largest ROM that fits between the load address and the end of memory.
+    pub fn program_capacity(&self) -> usize {
+        self.memory_size - self.load_address as usize
+    }
+}
+
+impl Default for MemoryLayout {
+    fn default() -> MemoryLayout {
+        MemoryLayout::CHIP8
+    }
+}
+
+fn parse_address(value: &str) -> Option<usize> {
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
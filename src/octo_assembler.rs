@@ -0,0 +1,1053 @@
+//! Assembler for Octo, the CHIP-8 assembly language Octo cartridges hold.
+//!
+//! It follows Octo's own compiler: labels, `:next`, aliases, constants,
+//! `:calc` expressions, macros, string modes, `:org`, `:unpack`, structured
+//! `if`/`else`/`end` and `loop`/`while`/`again`, the comparison pseudo-ops
+//! that go through vF, and the SUPER-CHIP and XO-CHIP instructions. Programs
+//! are assembled for 0x200 and start with a jump to `main` unless `main` is
+//! the first thing in the program.
+
+use std::collections::{HashMap, VecDeque};
+
+const PROGRAM_START: usize = 0x200;
+const MEMORY_SIZE: usize = 0x10000;
+// Limits that stop macros which expand themselves, and expressions deep
+// enough to overflow the stack
+const MAX_EXPANSIONS: usize = 0x10000;
+const MAX_TOKENS: usize = 1 << 20;
+const MAX_EXPRESSION_TOKENS: usize = 1024;
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+/// How a forward reference is patched once its label is defined.
+#[derive(Clone, Copy)]
+enum Patch {
+    Address, // Low 12 bits of the instruction at the address
+    Long,    // 16 bits at the address
+    Unpack,  // Low nibble of the first `vX := nn` at the address and all of the second
+}
+
+struct Fixup {
+    address: usize,
+    label: String,
+    line: usize,
+    patch: Patch,
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+    calls: u32,
+}
+
+/// One `:stringmode` declaration: its alphabet and what each character expands to.
+struct StringMode {
+    alphabet: Vec<char>,
+    body: Vec<Token>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operand {
+    Register(u16),
+    Value(u16),
+}
+
+struct Condition {
+    register: u16,
+    comparison: String,
+    operand: Option<Operand>, // None for `key` and `-key`
+}
+
+/// Assemble Octo source into a program loaded at 0x200.
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut assembler = Assembler::new(tokenize(source)?);
+    assembler.run().map_err(|e| match assembler.line {
+        0 => e,
+        line => format!("line {}: {}", line, e),
+    })?;
+    Ok(assembler.memory[PROGRAM_START..assembler.end].to_vec())
+}
+
+fn tokenize(source: &str) -> Result<VecDeque<Token>, String> {
+    let mut tokens = VecDeque::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+
+    while let Some(&c) = chars.peek() {
+        if c == '\n' {
+            line += 1;
+            chars.next();
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            while chars.next_if(|&c| c != '\n').is_some() {}
+        } else if c == '"' {
+            // Strings keep their opening quote so they can't be mistaken for names
+            chars.next();
+            let mut text = String::from('"');
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => text.push(match chars.next() {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('v') => '\x0B',
+                        Some('0') => '\0',
+                        Some(c) => c,
+                        None => return Err(format!("line {}: unterminated string", line)),
+                    }),
+                    Some(c) => {
+                        line += usize::from(c == '\n');
+                        text.push(c);
+                    }
+                    None => return Err(format!("line {}: unterminated string", line)),
+                }
+            }
+            tokens.push_back(Token { text, line });
+        } else {
+            let mut text = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                text.push(c);
+            }
+            tokens.push_back(Token { text, line });
+        }
+    }
+    Ok(tokens)
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    line: usize, // Line of the token being assembled, for errors
+    memory: Vec<u8>,
+    here: usize,
+    end: usize, // One past the highest byte written
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u16>,
+    macros: HashMap<String, Macro>,
+    string_modes: HashMap<String, Vec<StringMode>>,
+    fixups: Vec<Fixup>,
+    branches: Vec<usize>, // Jumps of open `if ... begin` and `else`, to patch at the next `else` or `end`
+    loops: Vec<(usize, Vec<usize>)>, // Start of each open loop and the jumps of its `while`s
+    jump_to_main: bool,   // 0x200 holds a jump to `main`
+    expansions: usize,    // Macros and string modes expanded so far
+}
+
+impl Assembler {
+    fn new(tokens: VecDeque<Token>) -> Assembler {
+        Assembler {
+            tokens,
+            line: 0,
+            memory: vec![0; MEMORY_SIZE],
+            here: PROGRAM_START + 2,
+            end: PROGRAM_START + 2,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            string_modes: HashMap::new(),
+            fixups: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+            jump_to_main: true,
+            expansions: 0,
+        }
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        while let Some(token) = self.tokens.pop_front() {
+            self.line = token.line;
+            self.statement(&token.text)?;
+        }
+        self.line = 0;
+
+        if !self.branches.is_empty() {
+            return Err("`if ... begin` without `end`".to_string());
+        }
+        if !self.loops.is_empty() {
+            return Err("`loop` without `again`".to_string());
+        }
+        if self.jump_to_main {
+            let main = *self
+                .labels
+                .get("main")
+                .ok_or("The program has no `: main` label")?;
+            self.write(PROGRAM_START, 0x1000 | main);
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(&value) = self.labels.get(&fixup.label) else {
+                return Err(format!(
+                    "line {}: undefined name {}",
+                    fixup.line, fixup.label
+                ));
+            };
+            let at = fixup.address;
+            match fixup.patch {
+                Patch::Address if value > 0xFFF => {
+                    return Err(format!(
+                        "line {}: {} is beyond 0xFFF; use `i := long`",
+                        fixup.line, fixup.label
+                    ));
+                }
+                Patch::Address => self.memory[at] |= (value >> 8) as u8,
+                Patch::Long => self.memory[at] = (value >> 8) as u8,
+                Patch::Unpack => self.memory[at] |= ((value >> 8) & 0xF) as u8,
+            }
+            match fixup.patch {
+                Patch::Unpack => self.memory[at + 2] = value as u8,
+                _ => self.memory[at + 1] = value as u8,
+            }
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self.tokens.pop_front().ok_or("Unexpected end of program")?;
+        self.line = token.line;
+        Ok(token.text)
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|token| token.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        match self.next()? {
+            token if token == text => Ok(()),
+            token => Err(format!("Expected {}, found {}", text, token)),
+        }
+    }
+
+    fn statement(&mut self, token: &str) -> Result<(), String> {
+        match token {
+            ":" => {
+                let name = self.name()?;
+                // Octo drops the jump to main when main comes first
+                if name == "main" && self.jump_to_main && self.here == PROGRAM_START + 2 {
+                    self.jump_to_main = false;
+                    self.here = PROGRAM_START;
+                    self.end = PROGRAM_START;
+                }
+                self.define_label(name, self.here)?;
+            }
+            ":next" => {
+                let name = self.name()?;
+                self.define_label(name, self.here + 1)?;
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.number()?;
+                self.constants.insert(name, value);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+            }
+            ":byte" => {
+                let value = self.byte()?;
+                self.emit_byte(value)?;
+            }
+            ":pointer" => {
+                let address = self.here;
+                let value = self.address(address, Patch::Long)?;
+                self.emit(value)?;
+            }
+            ":org" => self.here = self.number()? as usize % MEMORY_SIZE,
+            ":unpack" => {
+                let nibble = if self.peek_is("long") {
+                    self.next()?;
+                    0
+                } else {
+                    self.number()? as u16 & 0xF
+                };
+                let address = self.here;
+                let value = self.address(address, Patch::Unpack)?;
+                self.emit(0x6000 | (nibble << 4) | (value >> 8))?;
+                self.emit(0x6100 | (value & 0xFF))?;
+            }
+            ":call" => {
+                let address = self.here;
+                let value = self.address(address, Patch::Address)?;
+                self.emit(0x2000 | value)?;
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            ":assert" => {
+                let message = match self.tokens.front() {
+                    Some(token) if token.text.starts_with('"') => self.next()?[1..].to_string(),
+                    _ => "assertion failed".to_string(),
+                };
+                self.expect("{")?;
+                if self.calc()? == 0.0 {
+                    return Err(message);
+                }
+            }
+            ":macro" => self.define_macro()?,
+            ":stringmode" => self.define_string_mode()?,
+            ";" | "return" => self.emit(0x00EE)?,
+            "clear" => self.emit(0x00E0)?,
+            "hires" => self.emit(0x00FF)?,
+            "lores" => self.emit(0x00FE)?,
+            "exit" => self.emit(0x00FD)?,
+            "scroll-right" => self.emit(0x00FB)?,
+            "scroll-left" => self.emit(0x00FC)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n)?;
+            }
+            "audio" => self.emit(0xF002)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | (n << 8))?;
+            }
+            "bcd" => self.register_op(0xF033)?,
+            "saveflags" => self.register_op(0xF075)?,
+            "loadflags" => self.register_op(0xF085)?,
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    let low = if token == "save" { 2 } else { 3 };
+                    self.emit(0x5000 | (x << 8) | (y << 4) | low)?;
+                } else {
+                    let low = if token == "save" { 0x55 } else { 0x65 };
+                    self.emit(0xF000 | (x << 8) | low)?;
+                }
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | (x << 8) | (y << 4) | n)?;
+            }
+            "jump" | "jump0" | "native" => {
+                let address = self.here;
+                let value = self.address(address, Patch::Address)?;
+                let high = match token {
+                    "jump" => 0x1000,
+                    "jump0" => 0xB000,
+                    _ => 0x0000,
+                };
+                self.emit(high | value)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let low = match token {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.register_op(0xF000 | low)?;
+            }
+            "i" => self.index_op()?,
+            "if" => {
+                let condition = self.condition()?;
+                match self.next()?.as_str() {
+                    "then" => self.skip_unless(&condition)?,
+                    "begin" => {
+                        self.skip_unless(&negate(condition)?)?;
+                        self.branches.push(self.here);
+                        self.emit(0x1000)?;
+                    }
+                    other => return Err(format!("Expected then or begin, found {}", other)),
+                }
+            }
+            "else" => {
+                let branch = self.branches.pop().ok_or("`else` without `if ... begin`")?;
+                self.branches.push(self.here);
+                self.emit(0x1000)?;
+                self.patch_jump(branch, self.here)?;
+            }
+            "end" => {
+                let branch = self.branches.pop().ok_or("`end` without `if ... begin`")?;
+                self.patch_jump(branch, self.here)?;
+            }
+            "loop" => self.loops.push((self.here, Vec::new())),
+            "while" => {
+                let condition = self.condition()?;
+                self.skip_unless(&negate(condition)?)?;
+                let exit = self.here;
+                self.loops
+                    .last_mut()
+                    .ok_or("`while` outside a loop")?
+                    .1
+                    .push(exit);
+                self.emit(0x1000)?;
+            }
+            "again" => {
+                let (start, exits) = self.loops.pop().ok_or("`again` without `loop`")?;
+                self.emit(0x1000 | checked_address(start)?)?;
+                for exit in exits {
+                    self.patch_jump(exit, self.here)?;
+                }
+            }
+            _ if self.macros.contains_key(token) => self.expand_macro(token)?,
+            _ if self.string_modes.contains_key(token) => self.expand_string(token)?,
+            _ if self.register_name(token).is_some() => {
+                let x = self.register_name(token).unwrap_or_default();
+                self.register_statement(x)?;
+            }
+            _ if token == "{" || parse_number(token).is_some() => {
+                self.tokens.push_front(Token {
+                    text: token.to_string(),
+                    line: self.line,
+                });
+                let value = self.byte()?;
+                self.emit_byte(value)?;
+            }
+            _ => {
+                // Any other name calls a subroutine
+                self.tokens.push_front(Token {
+                    text: token.to_string(),
+                    line: self.line,
+                });
+                let address = self.here;
+                let value = self.address(address, Patch::Address)?;
+                self.emit(0x2000 | value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `vX := ...`, `vX += ...` and the other register statements.
+    fn register_statement(&mut self, x: u16) -> Result<(), String> {
+        let operator = self.next()?;
+        let vx = x << 8;
+
+        if operator == ":=" {
+            match self.next()?.as_str() {
+                "key" => return self.emit(0xF00A | vx),
+                "delay" => return self.emit(0xF007 | vx),
+                "random" => {
+                    let mask = self.byte()?;
+                    return self.emit(0xC000 | vx | mask);
+                }
+                token => self.tokens.push_front(Token {
+                    text: token.to_string(),
+                    line: self.line,
+                }),
+            }
+        }
+
+        match (operator.as_str(), self.operand()?) {
+            (":=", Operand::Value(value)) => self.emit(0x6000 | vx | value),
+            ("+=", Operand::Value(value)) => self.emit(0x7000 | vx | value),
+            ("-=", Operand::Value(value)) => self.emit(0x7000 | vx | (value.wrapping_neg() & 0xFF)),
+            (operator, Operand::Register(y)) => {
+                let low = match operator {
+                    ":=" => 0x0,
+                    "|=" => 0x1,
+                    "&=" => 0x2,
+                    "^=" => 0x3,
+                    "+=" => 0x4,
+                    "-=" => 0x5,
+                    ">>=" => 0x6,
+                    "=-" => 0x7,
+                    "<<=" => 0xE,
+                    _ => return Err(format!("Unknown register operator {}", operator)),
+                };
+                self.emit(0x8000 | vx | (y << 4) | low)
+            }
+            (operator, Operand::Value(_)) => {
+                Err(format!("{} needs a register on the right", operator))
+            }
+        }
+    }
+
+    /// `i := ...` and `i += vX`.
+    fn index_op(&mut self) -> Result<(), String> {
+        match self.next()?.as_str() {
+            "+=" => self.register_op(0xF01E),
+            ":=" => match self.next()?.as_str() {
+                "hex" => self.register_op(0xF029),
+                "bighex" => self.register_op(0xF030),
+                "long" => {
+                    self.emit(0xF000)?;
+                    let address = self.here;
+                    let value = self.address(address, Patch::Long)?;
+                    self.emit(value)
+                }
+                token => {
+                    self.tokens.push_front(Token {
+                        text: token.to_string(),
+                        line: self.line,
+                    });
+                    let address = self.here;
+                    let value = self.address(address, Patch::Address)?;
+                    self.emit(0xA000 | value)
+                }
+            },
+            operator => Err(format!("Unknown operator i {}", operator)),
+        }
+    }
+
+    /// An instruction of the form `?x??` taking one register.
+    fn register_op(&mut self, opcode: u16) -> Result<(), String> {
+        let x = self.register()?;
+        self.emit(opcode | (x << 8))
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let register = self.register()?;
+        let comparison = self.next()?;
+        let operand = match comparison.as_str() {
+            "key" | "-key" => None,
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => Some(self.operand()?),
+            _ => return Err(format!("Unknown comparison {}", comparison)),
+        };
+        Ok(Condition {
+            register,
+            comparison,
+            operand,
+        })
+    }
+
+    /// Emit code whose last instruction skips the next one unless the
+    /// condition holds. `<`, `>`, `<=` and `>=` subtract into vF first.
+    fn skip_unless(&mut self, condition: &Condition) -> Result<(), String> {
+        let vx = condition.register << 8;
+        let operand = condition.operand.unwrap_or(Operand::Value(0));
+
+        match (condition.comparison.as_str(), operand) {
+            ("key", _) => self.emit(0xE0A1 | vx),
+            ("-key", _) => self.emit(0xE09E | vx),
+            ("==", Operand::Value(value)) => self.emit(0x4000 | vx | value),
+            ("==", Operand::Register(y)) => self.emit(0x9000 | vx | (y << 4)),
+            ("!=", Operand::Value(value)) => self.emit(0x3000 | vx | value),
+            ("!=", Operand::Register(y)) => self.emit(0x5000 | vx | (y << 4)),
+            (comparison, operand) => {
+                let x = condition.register;
+                // vF = 1 when vX >= operand for < and >=, operand >= vX for > and <=
+                let at_least = matches!(comparison, "<" | ">=");
+                match (at_least, operand) {
+                    (true, Operand::Register(y)) => {
+                        self.emit(0x8F00 | (x << 4))?;
+                        self.emit(0x8F05 | (y << 4))?;
+                    }
+                    (true, Operand::Value(value)) => {
+                        self.emit(0x6F00 | value)?;
+                        self.emit(0x8F07 | (x << 4))?;
+                    }
+                    (false, Operand::Register(y)) => {
+                        self.emit(0x8F00 | (y << 4))?;
+                        self.emit(0x8F05 | (x << 4))?;
+                    }
+                    (false, Operand::Value(value)) => {
+                        self.emit(0x6F00 | value)?;
+                        self.emit(0x8F05 | (x << 4))?;
+                    }
+                }
+                let skip_when = if matches!(comparison, "<" | ">") {
+                    1
+                } else {
+                    0
+                };
+                self.emit(0x3F00 | skip_when)
+            }
+        }
+    }
+
+    fn define_label(&mut self, name: String, address: usize) -> Result<(), String> {
+        if self.labels.contains_key(&name) {
+            return Err(format!("Label {} is defined twice", name));
+        }
+        let address =
+            u16::try_from(address).map_err(|_| format!("Label {} is past 64 KiB", name))?;
+        self.labels.insert(name, address);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.name()?;
+        let mut parameters = Vec::new();
+        loop {
+            match self.next()? {
+                brace if brace == "{" => break,
+                parameter => parameters.push(parameter),
+            }
+        }
+        let body = self.block()?;
+        self.macros.insert(
+            name,
+            Macro {
+                parameters,
+                body,
+                calls: 0,
+            },
+        );
+        Ok(())
+    }
+
+    fn define_string_mode(&mut self) -> Result<(), String> {
+        let name = self.name()?;
+        let alphabet = self.string()?;
+        self.expect("{")?;
+        let body = self.block()?;
+        self.string_modes.entry(name).or_default().push(StringMode {
+            alphabet: alphabet.chars().collect(),
+            body,
+        });
+        Ok(())
+    }
+
+    /// Tokens up to the `}` matching an already read `{`.
+    fn block(&mut self) -> Result<Vec<Token>, String> {
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.tokens.pop_front().ok_or("Missing }")?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(body),
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), String> {
+        let count = self.macros[name].parameters.len();
+        let mut arguments = HashMap::new();
+        for index in 0..count {
+            let argument = self.tokens.pop_front().ok_or("Missing macro argument")?;
+            arguments.insert(self.macros[name].parameters[index].clone(), argument.text);
+        }
+
+        let definition = self.macros.get_mut(name).unwrap_or_else(|| unreachable!());
+        arguments.insert("CALLS".to_string(), definition.calls.to_string());
+        definition.calls += 1;
+        let body = definition.body.clone();
+        self.insert(body, &arguments)
+    }
+
+    fn expand_string(&mut self, name: &str) -> Result<(), String> {
+        let text = self.string()?;
+        let mut expansion = Vec::new();
+        for (index, char) in text.chars().enumerate() {
+            let (value, mode) = self.string_modes[name]
+                .iter()
+                .find_map(|mode| {
+                    let value = mode.alphabet.iter().position(|&c| c == char)?;
+                    Some((value, mode))
+                })
+                .ok_or(format!("String mode {} has no character {:?}", name, char))?;
+            let arguments = HashMap::from([
+                ("CHAR".to_string(), (char as u32).to_string()),
+                ("INDEX".to_string(), index.to_string()),
+                ("VALUE".to_string(), value.to_string()),
+            ]);
+            expansion.extend(mode.body.iter().map(|token| substitute(token, &arguments)));
+        }
+        self.insert(expansion, &HashMap::new())
+    }
+
+    /// Put the tokens of an expansion in front of the rest of the program.
+    fn insert(
+        &mut self,
+        tokens: Vec<Token>,
+        arguments: &HashMap<String, String>,
+    ) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS || self.tokens.len() + tokens.len() > MAX_TOKENS {
+            return Err("Macros expand without end; does one call itself?".to_string());
+        }
+        for token in tokens.iter().rev() {
+            self.tokens.push_front(substitute(token, arguments));
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let name = self.next()?;
+        if parse_number(&name).is_some() || name.starts_with('"') || name == "{" {
+            return Err(format!("Expected a name, found {}", name));
+        }
+        Ok(name)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let token = self.next()?;
+        token
+            .strip_prefix('"')
+            .map(str::to_string)
+            .ok_or(format!("Expected a string, found {}", token))
+    }
+
+    fn register_name(&self, token: &str) -> Option<u16> {
+        if let Some(&register) = self.aliases.get(token) {
+            return Some(register);
+        }
+        let digit = token.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u16::from_str_radix(digit, 16).ok()
+    }
+
+    fn register(&mut self) -> Result<u16, String> {
+        let token = self.next()?;
+        self.register_name(&token)
+            .ok_or(format!("Expected a register, found {}", token))
+    }
+
+    /// A register or an 8-bit value.
+    fn operand(&mut self) -> Result<Operand, String> {
+        if let Some(register) = self
+            .tokens
+            .front()
+            .and_then(|t| self.register_name(&t.text))
+        {
+            self.next()?;
+            return Ok(Operand::Register(register));
+        }
+        Ok(Operand::Value(self.byte()?))
+    }
+
+    /// A number, constant, label defined earlier or `{ expression }`.
+    fn number(&mut self) -> Result<f64, String> {
+        let token = self.next()?;
+        if token == "{" {
+            return self.calc();
+        }
+        self.lookup(&token)
+            .ok_or(format!("Expected a number, found {}", token))
+    }
+
+    fn lookup(&self, token: &str) -> Option<f64> {
+        parse_number(token)
+            .or_else(|| self.constants.get(token).copied())
+            .or_else(|| self.labels.get(token).map(|&address| address as f64))
+    }
+
+    fn byte(&mut self) -> Result<u16, String> {
+        let value = self.number()?.floor();
+        if !(-128.0..=255.0).contains(&value) {
+            return Err(format!("{} doesn't fit in a byte", value));
+        }
+        Ok(value as i64 as u16 & 0xFF)
+    }
+
+    fn nibble(&mut self) -> Result<u16, String> {
+        let value = self.number()?;
+        if !(0.0..=15.0).contains(&value) {
+            return Err(format!("{} doesn't fit in a nibble", value));
+        }
+        Ok(value as u16)
+    }
+
+    /// An address operand for the instruction at `at`. Names not defined yet
+    /// are patched in at the end.
+    fn address(&mut self, at: usize, patch: Patch) -> Result<u16, String> {
+        let token = self.next()?;
+        let value = if token == "{" {
+            self.calc()?
+        } else if let Some(value) = self.lookup(&token) {
+            value
+        } else if self.register_name(&token).is_some() || token.starts_with('"') {
+            return Err(format!("Expected an address, found {}", token));
+        } else {
+            self.fixups.push(Fixup {
+                address: at + if matches!(patch, Patch::Unpack) { 1 } else { 0 },
+                label: token,
+                line: self.line,
+                patch,
+            });
+            return Ok(0);
+        };
+
+        let value = value.floor() as i64;
+        let limit = if matches!(patch, Patch::Long) {
+            0xFFFF
+        } else {
+            0xFFF
+        };
+        if !(0..=limit).contains(&value) {
+            return Err(format!("Address {:#x} is out of range", value));
+        }
+        Ok(value as u16)
+    }
+
+    /// Evaluate a `:calc` expression after its `{`, up to the closing `}`.
+    /// Like Octo, operators have no precedence and group from the right.
+    fn calc(&mut self) -> Result<f64, String> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "}" {
+                break;
+            }
+            // Parentheses may be written against what they enclose
+            let inner = token.trim_start_matches('(').trim_end_matches(')');
+            let opening = token.len() - token.trim_start_matches('(').len();
+            let closing = token.trim_start_matches('(').len() - inner.len();
+            tokens.extend(std::iter::repeat_n("(".to_string(), opening));
+            if !inner.is_empty() {
+                tokens.push(inner.to_string());
+            }
+            tokens.extend(std::iter::repeat_n(")".to_string(), closing));
+            if tokens.len() > MAX_EXPRESSION_TOKENS {
+                return Err("Expression is too long".to_string());
+            }
+        }
+
+        let mut position = 0;
+        let value = self.expression(&tokens, &mut position)?;
+        if position != tokens.len() {
+            return Err(format!("Unexpected {} in expression", tokens[position]));
+        }
+        Ok(value)
+    }
+
+    fn expression(&self, tokens: &[String], position: &mut usize) -> Result<f64, String> {
+        let left = self.term(tokens, position)?;
+        let Some(operator) = tokens.get(*position).filter(|&t| t != ")") else {
+            return Ok(left);
+        };
+        *position += 1;
+        let right = self.expression(tokens, position)?;
+
+        let (a, b) = (left as i64, right as i64);
+        let boolean = |value: bool| if value { 1.0 } else { 0.0 };
+        Ok(match operator.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" | ">>" => {
+                let shifted = u32::try_from(b).ok().and_then(|b| match operator.as_str() {
+                    "<<" => a.checked_shl(b),
+                    _ => a.checked_shr(b),
+                });
+                shifted.ok_or(format!("Can't shift by {}", b))? as f64
+            }
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => boolean(left < right),
+            ">" => boolean(left > right),
+            "<=" => boolean(left <= right),
+            ">=" => boolean(left >= right),
+            "==" => boolean(left == right),
+            "!=" => boolean(left != right),
+            _ => return Err(format!("Unknown operator {} in expression", operator)),
+        })
+    }
+
+    fn term(&self, tokens: &[String], position: &mut usize) -> Result<f64, String> {
+        let token = tokens.get(*position).ok_or("Incomplete expression")?;
+        *position += 1;
+
+        if token == "(" {
+            let value = self.expression(tokens, position)?;
+            if tokens.get(*position).is_none_or(|t| t != ")") {
+                return Err("Missing ) in expression".to_string());
+            }
+            *position += 1;
+            return Ok(value);
+        }
+        if token == "strlen" {
+            let text = tokens.get(*position).and_then(|t| t.strip_prefix('"'));
+            *position += 1;
+            return Ok(text.ok_or("strlen needs a string")?.chars().count() as f64);
+        }
+
+        let unary: Option<fn(f64) -> f64> = match token.as_str() {
+            "-" => Some(|x| -x),
+            "~" => Some(|x| !(x as i64) as f64),
+            "!" => Some(|x| if x == 0.0 { 1.0 } else { 0.0 }),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(function) = unary {
+            return Ok(function(self.term(tokens, position)?));
+        }
+        if token == "@" {
+            let address = self.term(tokens, position)? as usize;
+            return Ok(self.memory[address % MEMORY_SIZE] as f64);
+        }
+
+        match token.as_str() {
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => self
+                .lookup(token)
+                .ok_or(format!("Unknown name {} in expression", token)),
+        }
+    }
+
+    fn emit(&mut self, opcode: u16) -> Result<(), String> {
+        self.emit_byte(opcode >> 8)?;
+        self.emit_byte(opcode & 0xFF)
+    }
+
+    fn emit_byte(&mut self, byte: u16) -> Result<(), String> {
+        if self.here >= MEMORY_SIZE {
+            return Err("Program doesn't fit in 64 KiB".to_string());
+        }
+        self.memory[self.here] = byte as u8;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    fn write(&mut self, address: usize, opcode: u16) {
+        self.memory[address] = (opcode >> 8) as u8;
+        self.memory[address + 1] = opcode as u8;
+    }
+
+    /// Point the jump placeholder at `jump` to `target`.
+    fn patch_jump(&mut self, jump: usize, target: usize) -> Result<(), String> {
+        self.write(jump, 0x1000 | checked_address(target)?);
+        Ok(())
+    }
+}
+
+fn checked_address(address: usize) -> Result<u16, String> {
+    match u16::try_from(address) {
+        Ok(address) if address <= 0xFFF => Ok(address),
+        _ => Err(format!("Jump target {:#x} is beyond 0xFFF", address)),
+    }
+}
+
+/// The condition that holds exactly when `condition` doesn't.
+fn negate(condition: Condition) -> Result<Condition, String> {
+    let comparison = match condition.comparison.as_str() {
+        "==" => "!=",
+        "!=" => "==",
+        "<" => ">=",
+        ">=" => "<",
+        ">" => "<=",
+        "<=" => ">",
+        "key" => "-key",
+        _ => "key",
+    };
+    Ok(Condition {
+        comparison: comparison.to_string(),
+        ..condition
+    })
+}
+
+fn substitute(token: &Token, arguments: &HashMap<String, String>) -> Token {
+    Token {
+        text: arguments
+            .get(&token.text)
+            .cloned()
+            .unwrap_or_else(|| token.text.clone()),
+        line: token.line,
+    }
+}
+
+fn parse_number(token: &str) -> Option<f64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value } as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+
+    #[test]
+    fn main_first_needs_no_jump() {
+        let program = assemble(": main v0 := 5 loop v0 -= 1 while v0 != 0 again ;").unwrap();
+        assert_eq!(
+            program,
+            [
+                0x60, 0x05, 0x70, 0xFF, 0x40, 0x00, 0x12, 0x0A, 0x12, 0x02, 0x00, 0xEE
+            ]
+        );
+    }
+
+    #[test]
+    fn forward_references_and_branches() {
+        let source = ": sub v1 += 2 ; : main sub if v2 < v3 begin clear else return end \
+            i := data sprite v0 v1 1 : data 0xFF";
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program,
+            [
+                0x12, 0x06, 0x71, 0x02, 0x00, 0xEE, 0x22, 0x02, 0x8F, 0x20, 0x8F, 0x35, 0x3F, 0x00,
+                0x12, 0x14, 0x00, 0xE0, 0x12, 0x16, 0x00, 0xEE, 0xA2, 0x1A, 0xD0, 0x11, 0xFF
+            ]
+        );
+    }
+
+    #[test]
+    fn macros_constants_and_string_modes() {
+        let source = ":const N 7 :macro twice A { A A } \
+            :stringmode s \"AB\" { :byte { VALUE + 10 } } : main v4 := N twice clear s \"BA\"";
+        let program = assemble(source).unwrap();
+        assert_eq!(program, [0x64, 0x07, 0x00, 0xE0, 0x00, 0xE0, 0x0B, 0x0A]);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(
+            assemble(": main\n  foo").unwrap_err(),
+            "line 2: undefined name foo"
+        );
+        assert_eq!(
+            assemble(": main\nv0 := 300").unwrap_err(),
+            "line 2: 300 doesn't fit in a byte"
+        );
+    }
+
+    #[test]
+    fn hostile_source_is_an_error() {
+        assert!(assemble(":calc x { 1 << 64 } : main").is_err());
+        assert!(assemble(":calc x { 1 >> -1 } : main").is_err());
+        assert!(assemble(":macro m { m } : main m").is_err());
+        assert!(assemble(":macro m { m m } : main m").is_err());
+        let deep = format!(":calc x {{ {} 1 }} : main", "- ".repeat(100_000));
+        assert!(assemble(&deep).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+//! Octo "cartridge" GIFs, which carry a program and its settings in the image data.
+//!
+//! Every pixel's palette index holds two bits of payload in its low bits, most
+//! significant pair first, read across all frames in order. The payload is a
+//! 32-bit big-endian length followed by that many bytes of JSON:
+//! `{"program": "<Octo source>", "options": {...}}`.
+
+use serde::Deserialize;
+
+use crate::gif;
+use crate::octo_assembler;
+use crate::palette::Palette;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::rom_database::{GameKeys, RomInfo};
+
+pub use crate::gif::is_gif as is_cartridge;
+
+/// A program unpacked from a cartridge, with its settings as a `RomInfo`.
+pub struct Cartridge {
+    pub program: Vec<u8>,
+    pub info: RomInfo,
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    program: String,
+    #[serde(default)]
+    options: Options,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Options {
+    tickrate: Option<u32>,
+    max_size: Option<usize>,
+    shift_quirks: Option<bool>,
+    load_store_quirks: Option<bool>,
+    clip_quirks: Option<bool>,
+    jump_quirks: Option<bool>,
+    logic_quirks: Option<bool>,
+    v_blank_quirks: Option<bool>,
+    background_color: Option<String>,
+    fill_color: Option<String>,
+    fill_color2: Option<String>,
+    blend_color: Option<String>,
+}
+
+impl Cartridge {
+    /// Unpack a cartridge; `name` becomes the title shown in the window.
+    pub fn parse(data: &[u8], name: &str) -> Result<Cartridge, String> {
+        let frames = gif::decode_frames(data)?;
+
+        let bytes: Vec<u8> = frames
+            .iter()
+            .flat_map(|frame| frame.pixels.chunks_exact(4))
+            .map(|pixels| {
+                pixels
+                    .iter()
+                    .fold(0, |byte, &pixel| (byte << 2) | (pixel & 0x03))
+            })
+            .collect();
+
+        let length = bytes
+            .get(..4)
+            .map(|length| u32::from_be_bytes([length[0], length[1], length[2], length[3]]))
+            .ok_or("Cartridge holds no data")? as usize;
+        let json = bytes
+            .get(4..4 + length)
+            .ok_or("Cartridge data is truncated")?;
+        let payload: Payload = serde_json::from_slice(json)
+            .map_err(|e| format!("Cartridge data is not an Octo payload: {}", e))?;
+
+        Ok(Cartridge {
+            program: octo_assembler::assemble(&payload.program)
+                .map_err(|e| format!("Could not assemble the cartridge program: {}", e))?,
+            info: payload.options.to_info(name),
+        })
+    }
+}
+
+impl Options {
+    fn to_info(&self, name: &str) -> RomInfo {
+        let platform = self.max_size.map(|max_size| match max_size {
+            0..=3216 => Platform::Chip8,
+            3217..=3584 => Platform::SuperChip,
+            _ => Platform::XoChip,
+        });
+
+        // Octo's quirk flags are all off for its own defaults, which match XO-CHIP
+        let mut quirks = Quirks::XOCHIP;
+        let mut any_quirks = false;
+        let mut set = |flag: Option<bool>, quirk: &mut bool, inverted: bool| {
+            if let Some(flag) = flag {
+                *quirk = flag != inverted;
+                any_quirks = true;
+            }
+        };
+        set(self.shift_quirks, &mut quirks.shifting, false);
+        set(self.load_store_quirks, &mut quirks.memory, true);
+        set(self.clip_quirks, &mut quirks.clipping, false);
+        set(self.jump_quirks, &mut quirks.jumping, false);
+        set(self.logic_quirks, &mut quirks.vf_reset, false);
+        set(self.v_blank_quirks, &mut quirks.display_wait, false);
+
+        let palette = [
+            &self.background_color,
+            &self.fill_color,
+            &self.fill_color2,
+            &self.blend_color,
+        ]
+        .into_iter()
+        .map(Option::as_deref)
+        .collect::<Option<Vec<_>>>()
+        .and_then(|colors| Palette::parse(&colors.join(",")).ok());
+
+        RomInfo {
+            title: name.to_string(),
+            authors: Vec::new(),
+            platform,
+            quirks: any_quirks.then_some(quirks),
+            speed: self.tickrate.filter(|&tickrate| tickrate > 0),
+            keys: GameKeys::default(),
+            palette,
+        }
+    }
+}
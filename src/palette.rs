@@ -0,0 +1,140 @@
+use std::fs;
+use std::path::Path;
+
+use sdl2::pixels::Color;
+
+use crate::data_dir;
+
+// Built-in themes as (name, [background, plane 1, plane 2, both planes])
+const THEMES: [(&str, [u32; 4]); 7] = [
+    ("classic", [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+    ("amber", [0x1A0F00, 0xFFB000, 0xCC7A00, 0xFFD980]),
+    ("green", [0x001A00, 0x33FF33, 0x1F9F1F, 0xB3FFB3]),
+    ("lcd", [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F]),
+    ("high-contrast", [0x000000, 0xFFFF00, 0x00FFFF, 0xFFFFFF]),
+    ("colorblind", [0x000000, 0xE69F00, 0x56B4E9, 0xF0E442]), // Okabe-Ito
+    ("colorblind-light", [0xFFFFFF, 0x0072B2, 0xD55E00, 0x000000]),
+];
+
+/// Colors used to draw pixel values: 2 for classic modes, 4 or 16 for multi-plane modes.
+#[derive(Clone)]
+pub struct Palette {
+    name: String,
+    colors: Vec<Color>,
+}
+
+impl Palette {
+    /// Parse a theme name or a comma separated list of 2, 4 or 16 hex colors.
+    pub fn parse(spec: &str) -> Result<Palette, String> {
+        if let Some(palette) = Palette::named(spec) {
+            return Ok(palette);
+        }
+        if !spec.contains(',') {
+            return Err(format!(
+                "Unknown palette {} (themes: {})",
+                spec,
+                THEMES.map(|(name, _)| name).join(", ")
+            ));
+        }
+
+        let colors = spec
+            .split(',')
+            .map(|color| parse_color(color.trim()))
+            .collect::<Result<Vec<Color>, String>>()?;
+
+        if ![2, 4, 16].contains(&colors.len()) {
+            return Err(format!("Palette {} needs 2, 4 or 16 colors", spec));
+        }
+
+        Ok(Palette {
+            name: "custom".to_string(),
+            colors,
+        })
+    }
+
+    /// Read a palette file holding what `--palette` takes, with colors either
+    /// on one line or one per line. Lines starting with `#` that aren't a
+    /// color are comments.
+    pub fn load(path: &Path) -> Result<Palette, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read palette {}: {}", path.display(), e))?;
+        let spec: Vec<&str> = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| {
+                !line.is_empty() && (!line.starts_with('#') || parse_color(line).is_ok())
+            })
+            .collect();
+        Palette::parse(&spec.join(",")).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// The palette in `<rom>.palette`, if there is one.
+    pub fn for_rom(rom_name: &str) -> Result<Option<Palette>, String> {
+        let path = format!("{}.palette", rom_name);
+        let path = Path::new(&path);
+        path.exists().then(|| Palette::load(path)).transpose()
+    }
+
+    /// The palette in `palette` in the user data directory, used for ROMs
+    /// without a palette of their own.
+    pub fn user_default() -> Result<Option<Palette>, String> {
+        let Some(path) = data_dir::user_data_dir().map(|dir| dir.join("palette")) else {
+            return Ok(None);
+        };
+        path.exists().then(|| Palette::load(&path)).transpose()
+    }
+
+    pub fn named(name: &str) -> Option<Palette> {
+        THEMES
+            .iter()
+            .find(|(theme, _)| *theme == name)
+            .map(|&(theme, colors)| Palette {
+                name: theme.to_string(),
+                colors: colors.iter().map(|&rgb| rgb_color(rgb)).collect(),
+            })
+    }
+
+    /// The built-in theme after this one, wrapping around.
+    pub fn next(&self) -> Palette {
+        let index = THEMES
+            .iter()
+            .position(|(theme, _)| *theme == self.name)
+            .map_or(0, |index| (index + 1) % THEMES.len());
+        Palette::named(THEMES[index].0).unwrap()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Color for a pixel value, which is a bitmask of the planes it is set in.
+    pub fn color(&self, pixel: u8) -> Color {
+        self.colors[pixel as usize % self.colors.len()]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::named("classic").unwrap()
+    }
+}
+
+fn parse_color(color: &str) -> Result<Color, String> {
+    let hex = color.trim_start_matches('#');
+    let rgb = u32::from_str_radix(hex, 16).map_err(|_| format!("Invalid color {}", color))?;
+
+    match hex.len() {
+        6 => Ok(rgb_color(rgb)),
+        // #RGB shorthand
+        3 => Ok(Color::RGB(
+            ((rgb >> 8) & 0xF) as u8 * 0x11,
+            ((rgb >> 4) & 0xF) as u8 * 0x11,
+            (rgb & 0xF) as u8 * 0x11,
+        )),
+        _ => Err(format!("Invalid color {}", color)),
+    }
+}
+
+fn rgb_color(rgb: u32) -> Color {
+    Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+}
@@ -0,0 +1,81 @@
+use crate::USER_FLAGS;
+use crate::fontset::Fontset;
+use crate::memory_layout::MemoryLayout;
+use crate::quirks::Quirks;
+
+/// Machine a program was written for.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub enum Platform {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn parse(name: &str) -> Result<Platform, String> {
+        match name {
+            "chip8" | "vip" => Ok(Platform::Chip8),
+            "schip" | "superchip" => Ok(Platform::SuperChip),
+            "xochip" => Ok(Platform::XoChip),
+            _ => Err(format!("Unknown platform {} (chip8, schip, xochip)", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    /// Quirks used when no profile is chosen explicitly.
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::CHIP8,
+            Platform::SuperChip => Quirks::SCHIP,
+            Platform::XoChip => Quirks::XOCHIP,
+        }
+    }
+
+    /// Font used when none is chosen explicitly.
+    pub fn fontset(&self) -> Fontset {
+        let name = match self {
+            Platform::Chip8 => "fishnchips",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "octo",
+        };
+        Fontset::named(name).unwrap()
+    }
+
+    /// Number of RPL user flags Fx75/Fx85 can use.
+    pub fn user_flags(&self) -> usize {
+        match self {
+            Platform::XoChip => 16,
+            _ => USER_FLAGS,
+        }
+    }
+
+    /// Warning for a ROM that fits in memory but not on the original hardware.
+    pub fn size_warning(&self, rom_size: usize, layout: &MemoryLayout) -> Option<String> {
+        // The COSMAC VIP keeps its stack and display buffer in the top 352 bytes
+        const VIP_STACK_START: usize = 0xEA0;
+        let program_space = VIP_STACK_START.saturating_sub(layout.load_address as usize);
+
+        (*self == Platform::Chip8 && rom_size > program_space).then(|| {
+            format!(
+                "ROM is {} bytes; on a COSMAC VIP only {} bytes fit between {:#05x} and the stack and display",
+                rom_size, program_space, layout.load_address
+            )
+        })
+    }
+
+    /// Memory layout used when none is chosen explicitly.
+    pub fn memory_layout(&self) -> MemoryLayout {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => MemoryLayout::CHIP8,
+            Platform::XoChip => MemoryLayout::XOCHIP,
+        }
+    }
+}
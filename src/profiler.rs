@@ -0,0 +1,210 @@
+//! Counts where a program spends its instructions.
+
+use std::collections::HashMap;
+
+use crate::disassembler;
+use crate::symbols::Symbols;
+
+// Entries shown in each part of the report
+const REPORT_ROWS: usize = 20;
+// A loop from one Fx07 back to the same Fx07 of at most this many
+// instructions is taken to be waiting for the delay timer
+const MAX_POLL_LOOP: u64 = 4;
+
+/// A loop closed by a backward `1nnn` or `Bnnn` jump.
+#[derive(Default)]
+struct Loop {
+    iterations: u64,   // Times the backward jump was taken
+    instructions: u64, // Instructions run inside the loop, including calls from it
+}
+
+/// A loop the program is in, with the call depth of its code.
+struct ActiveLoop {
+    span: (u16, u16),
+    depth: u32,
+}
+
+/// Instruction counts per address, opcode class and loop, collected while a
+/// `Chip8` runs.
+pub struct Profiler {
+    counts: Vec<u64>,                    // Executions per address
+    classes: HashMap<&'static str, u64>, // Executions per opcode class
+    loops: HashMap<(u16, u16), Loop>,    // Loops by (jump target, jump address)
+    active: Vec<ActiveLoop>,             // Loops entered and not yet left
+    depth: u32,                          // Subroutine calls not yet returned from
+    instructions: u64,
+    frames: u64,
+    last_poll: Option<(u16, u64)>, // Address and instruction number of the last Fx07
+    polling: u64,                  // Instructions spent in delay timer polling loops
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            counts: vec![0; 0x10000],
+            classes: HashMap::new(),
+            loops: HashMap::new(),
+            active: Vec::new(),
+            depth: 0,
+            instructions: 0,
+            frames: 0,
+            last_poll: None,
+            polling: 0,
+        }
+    }
+
+    /// Count an instruction at `pc` that continued at `next_pc`.
+    pub fn record(&mut self, pc: u16, opcode: u16, next_pc: u16) {
+        self.counts[pc as usize] += 1;
+        *self
+            .classes
+            .entry(disassembler::opcode_class(opcode))
+            .or_default() += 1;
+        self.record_loops(pc, opcode, next_pc);
+
+        if opcode & 0xF0FF == 0xF007 {
+            if let Some((address, at)) = self.last_poll
+                && address == pc
+                && self.instructions - at <= MAX_POLL_LOOP
+            {
+                self.polling += self.instructions - at;
+            }
+            self.last_poll = Some((pc, self.instructions));
+        }
+        self.instructions += 1;
+    }
+
+    /// Attribute the instruction to the loops it ran in. The program stays in
+    /// a loop while it runs code inside the loop's span or subroutines called
+    /// from there; a loop's first pass is not counted, as it is only known to
+    /// be one once its jump back is taken.
+    fn record_loops(&mut self, pc: u16, opcode: u16, next_pc: u16) {
+        let depth = self.depth;
+        self.active.retain(|active| {
+            let (start, end) = active.span;
+            depth > active.depth || (depth == active.depth && (start..=end).contains(&pc))
+        });
+        for active in &self.active {
+            if let Some(counts) = self.loops.get_mut(&active.span) {
+                counts.instructions += 1;
+            }
+        }
+
+        match opcode & 0xF000 {
+            0x2000 => self.depth += 1,
+            0x0000 if opcode == 0x00EE => self.depth = self.depth.saturating_sub(1),
+            0x1000 | 0xB000 if next_pc <= pc => {
+                let span = (next_pc, pc);
+                let counts = self.loops.entry(span).or_default();
+                counts.iterations += 1;
+                if !self.active.iter().any(|active| active.span == span) {
+                    counts.instructions += 1;
+                    self.active.push(ActiveLoop { span, depth });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    /// Ranked report of hot spots, opcode classes and loops. `memory` is used
+    /// to disassemble the hot addresses, `symbols` to name them.
+    pub fn report(&self, memory: &[u8], symbols: &Symbols) -> String {
+        let total = self.instructions.max(1) as f64;
+        let share = |count: u64| 100.0 * count as f64 / total;
+        let disassemble = |address: usize| {
+            let byte = |offset: usize| memory[(address + offset) % memory.len()] as u16;
+            disassembler::mnemonic((byte(0) << 8) | byte(1), symbols)
+        };
+
+        let mut lines = vec![format!(
+            "Profile: {} instructions in {} frames ({:.1} per frame)",
+            self.instructions,
+            self.frames,
+            self.instructions as f64 / self.frames.max(1) as f64
+        )];
+
+        lines.push(String::new());
+        lines.push("Hot spots:".to_string());
+        let mut addresses: Vec<usize> = (0..self.counts.len())
+            .filter(|&address| self.counts[address] > 0)
+            .collect();
+        addresses.sort_by_key(|&address| std::cmp::Reverse(self.counts[address]));
+        addresses.truncate(REPORT_ROWS);
+        let names: Vec<String> = addresses
+            .iter()
+            .map(|&address| symbols.name(address as u16))
+            .collect();
+        let width = names.iter().map(String::len).max().unwrap_or(0);
+        for (rank, (&address, name)) in addresses.iter().zip(&names).enumerate() {
+            let count = self.counts[address];
+            lines.push(format!(
+                "  {:>3}. {:<width$}  {:>12}  {:>5.1}%  {}",
+                rank + 1,
+                name,
+                count,
+                share(count),
+                disassemble(address)
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push("Opcode classes:".to_string());
+        let mut classes: Vec<_> = self.classes.iter().collect();
+        classes.sort_by_key(|&(class, count)| (std::cmp::Reverse(*count), *class));
+        for (class, &count) in classes {
+            lines.push(format!(
+                "  {}  {:>12}  {:>5.1}%",
+                class,
+                count,
+                share(count)
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push("Hottest loops:".to_string());
+        let mut loops: Vec<(u16, u16, u64, u64)> = self
+            .loops
+            .iter()
+            .map(|(&(start, end), counts)| (start, end, counts.iterations, counts.instructions))
+            .collect();
+        loops.sort_by_key(|&(start, _, _, instructions)| (std::cmp::Reverse(instructions), start));
+        loops.truncate(REPORT_ROWS);
+        let spans: Vec<String> = loops
+            .iter()
+            .map(|&(start, end, _, _)| format!("{}-{}", symbols.name(start), symbols.name(end)))
+            .collect();
+        let width = spans.iter().map(String::len).max().unwrap_or(0);
+        for (&(start, _, iterations, instructions), span) in loops.iter().zip(&spans) {
+            lines.push(format!(
+                "  {:<width$}  {:>10} iterations  {:>5.1}%  {}",
+                span,
+                iterations,
+                share(instructions),
+                disassemble(start as usize)
+            ));
+        }
+        if loops.is_empty() {
+            lines.push("  none".to_string());
+        }
+
+        lines.push(String::new());
+        lines.push(format!(
+            "Busy-waiting on the delay timer (Fx07 polling loops): {:.1}% of instructions, \
+             {:.1} useful instructions per frame",
+            share(self.polling),
+            (self.instructions - self.polling) as f64 / self.frames.max(1) as f64
+        ));
+
+        lines.join("\n")
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
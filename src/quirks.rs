@@ -0,0 +1,122 @@
+// Quirk names as used by `--quirks` toggles, in field order
+const QUIRK_NAMES: [&str; 6] = [
+    "vf_reset",
+    "memory",
+    "shifting",
+    "jumping",
+    "clipping",
+    "display_wait",
+];
+
+/// Behaviors that differ between CHIP-8 interpreters.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Quirks {
+    pub vf_reset: bool,     // 8xy1/8xy2/8xy3 reset VF to 0
+    pub memory: bool,       // Fx55/Fx65 increment I
+    pub shifting: bool,     // 8xy6/8xyE shift Vx in place instead of Vy
+    pub jumping: bool,      // Bnnn jumps to xnn + Vx instead of nnn + V0
+    pub clipping: bool,     // Sprites clip at the screen edges instead of wrapping
+    pub display_wait: bool, // Dxyn waits for the next frame before drawing again
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter.
+    pub const CHIP8: Quirks = Quirks {
+        vf_reset: true,
+        memory: true,
+        shifting: false,
+        jumping: false,
+        clipping: true,
+        display_wait: true,
+    };
+
+    /// SUPER-CHIP 1.1 on the HP-48.
+    pub const SCHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory: false,
+        shifting: true,
+        jumping: true,
+        clipping: true,
+        display_wait: false,
+    };
+
+    /// XO-CHIP as implemented by Octo.
+    pub const XOCHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory: true,
+        shifting: false,
+        jumping: false,
+        clipping: false,
+        display_wait: false,
+    };
+
+    pub fn profile(name: &str) -> Option<Quirks> {
+        match name {
+            "chip8" | "vip" => Some(Quirks::CHIP8),
+            "schip" | "superchip" => Some(Quirks::SCHIP),
+            "xochip" => Some(Quirks::XOCHIP),
+            _ => None,
+        }
+    }
+
+    /// Parse a profile name followed by optional toggles, e.g. `schip,-clipping,+vf_reset`.
+    pub fn parse(spec: &str) -> Result<Quirks, String> {
+        let mut parts = spec.split(',').map(str::trim);
+        let profile = parts.next().unwrap_or_default();
+        let mut quirks = Quirks::profile(profile).ok_or(format!(
+            "Unknown quirk profile {} (chip8, schip, xochip)",
+            profile
+        ))?;
+
+        for toggle in parts {
+            let (enabled, name) = match toggle.split_at_checked(1) {
+                Some(("+", name)) => (true, name),
+                Some(("-", name)) => (false, name),
+                _ => return Err(format!("Quirk toggle {} must start with + or -", toggle)),
+            };
+            *quirks.flag_mut(name).ok_or(format!(
+                "Unknown quirk {} ({})",
+                name,
+                QUIRK_NAMES.join(", ")
+            ))? = enabled;
+        }
+
+        Ok(quirks)
+    }
+
+    /// Names of the quirks that are switched on.
+    pub fn enabled(&self) -> Vec<&'static str> {
+        let flags = [
+            self.vf_reset,
+            self.memory,
+            self.shifting,
+            self.jumping,
+            self.clipping,
+            self.display_wait,
+        ];
+        QUIRK_NAMES
+            .iter()
+            .zip(flags)
+            .filter(|&(_, enabled)| enabled)
+            .map(|(&name, _)| name)
+            .collect()
+    }
+
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "vf_reset" => Some(&mut self.vf_reset),
+            "memory" => Some(&mut self.memory),
+            "shifting" => Some(&mut self.shifting),
+            "jumping" => Some(&mut self.jumping),
+            "clipping" => Some(&mut self.clipping),
+            "display_wait" => Some(&mut self.display_wait),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::CHIP8
+    }
+}
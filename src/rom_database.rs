@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::data_dir;
+use crate::palette::Palette;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+
+// programs.json from the chip-8-database project, compiled into the binary
+const BUNDLED_DATABASE: &str = include_str!("../data/programs.json");
+
+/// Keypad keys a program uses for directions and its two action buttons.
+#[derive(Clone, Copy, Default)]
+pub struct GameKeys {
+    pub up: Option<u8>,
+    pub down: Option<u8>,
+    pub left: Option<u8>,
+    pub right: Option<u8>,
+    pub a: Option<u8>,
+    pub b: Option<u8>,
+}
+
+/// Settings the database knows for a single ROM.
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub speed: Option<u32>,
+    pub keys: GameKeys,
+    pub palette: Option<Palette>,
+}
+
+impl RomInfo {
+    /// `Title by Author`, for the window title.
+    pub fn label(&self) -> String {
+        if self.authors.is_empty() {
+            self.title.clone()
+        } else {
+            format!("{} by {}", self.title, self.authors.join(", "))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, RomEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RomEntry {
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, QuirkOverrides>,
+    tickrate: Option<u32>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QuirkOverrides {
+    shift: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    vblank: Option<bool>,
+    logic: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+/// ROMs indexed by the lowercase hex SHA-1 of their contents.
+pub struct RomDatabase {
+    programs: Vec<Program>,
+    index: HashMap<String, (usize, String)>, // ROM hash to program and its `roms` key
+}
+
+impl RomDatabase {
+    /// The database compiled into the emulator.
+    pub fn bundled() -> Result<RomDatabase, String> {
+        RomDatabase::parse(BUNDLED_DATABASE).map_err(|e| format!("Bundled ROM database: {}", e))
+    }
+
+    /// Where a copy of the chip-8-database is picked up without `--database`:
+    /// `programs.json` in the user data directory.
+    pub fn installed_path() -> Option<PathBuf> {
+        data_dir::user_data_dir().map(|dir| dir.join("programs.json"))
+    }
+
+    /// The database at `installed_path`, if one was put there to replace the
+    /// bundled one.
+    pub fn installed() -> Result<Option<RomDatabase>, String> {
+        match RomDatabase::installed_path().filter(|path| path.exists()) {
+            Some(path) => RomDatabase::load(&path.to_string_lossy()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Load a `programs.json` file, e.g. a newer copy of the chip-8-database.
+    pub fn load(path: &str) -> Result<RomDatabase, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read ROM database {}: {}", path, e))?;
+        RomDatabase::parse(&json).map_err(|e| format!("ROM database {}: {}", path, e))
+    }
+
+    fn parse(json: &str) -> Result<RomDatabase, String> {
+        let programs: Vec<Program> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+        let mut index = HashMap::new();
+        for (program_index, program) in programs.iter().enumerate() {
+            for hash in program.roms.keys() {
+                index.insert(hash.to_lowercase(), (program_index, hash.clone()));
+            }
+        }
+
+        Ok(RomDatabase { programs, index })
+    }
+
+    /// Look up a ROM by its contents.
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo> {
+        let (program_index, key) = self.index.get(&rom_hash(rom))?;
+        let program = &self.programs[*program_index];
+        let entry = &program.roms[key];
+
+        // The first listed platform this emulator can run
+        let platform = entry
+            .platforms
+            .iter()
+            .find_map(|id| Some((id, database_platform(id)?)));
+
+        let quirks = platform.map(|(id, (_, quirks))| match entry.quirky_platforms.get(id) {
+            Some(overrides) => overrides.apply(quirks),
+            None => quirks,
+        });
+
+        let key = |name: &str| entry.keys.get(name).copied().filter(|&key| key < 16);
+
+        let palette = entry
+            .colors
+            .as_ref()
+            .filter(|colors| !colors.pixels.is_empty())
+            .and_then(|colors| Palette::parse(&colors.pixels.join(",")).ok());
+
+        Some(RomInfo {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            platform: platform.map(|(_, (platform, _))| platform),
+            quirks,
+            speed: entry.tickrate.filter(|&tickrate| tickrate > 0),
+            keys: GameKeys {
+                up: key("up"),
+                down: key("down"),
+                left: key("left"),
+                right: key("right"),
+                a: key("a"),
+                b: key("b"),
+            },
+            palette,
+        })
+    }
+}
+
+impl QuirkOverrides {
+    fn apply(&self, mut quirks: Quirks) -> Quirks {
+        if let Some(shift) = self.shift {
+            quirks.shifting = shift;
+        }
+        if let Some(leave_i_unchanged) = self.memory_leave_i_unchanged {
+            quirks.memory = !leave_i_unchanged;
+        }
+        if let Some(wrap) = self.wrap {
+            quirks.clipping = !wrap;
+        }
+        if let Some(jump) = self.jump {
+            quirks.jumping = jump;
+        }
+        if let Some(vblank) = self.vblank {
+            quirks.display_wait = vblank;
+        }
+        if let Some(logic) = self.logic {
+            quirks.vf_reset = logic;
+        }
+        quirks
+    }
+}
+
+/// Lowercase hex SHA-1, the key used by the database.
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+/// Platform and quirks for a database platform id, if it can be emulated.
+fn database_platform(id: &str) -> Option<(Platform, Quirks)> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some((Platform::Chip8, Quirks::CHIP8)),
+        "modernChip8" => Some((
+            Platform::Chip8,
+            Quirks {
+                vf_reset: false,
+                display_wait: false,
+                ..Quirks::CHIP8
+            },
+        )),
+        "chip48" => Some((
+            Platform::Chip8,
+            Quirks {
+                memory: true,
+                ..Quirks::SCHIP
+            },
+        )),
+        "superchip1" | "superchip" => Some((Platform::SuperChip, Quirks::SCHIP)),
+        "xochip" => Some((Platform::XoChip, Quirks::XOCHIP)),
+        _ => None,
+    }
+}
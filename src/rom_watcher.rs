@@ -0,0 +1,55 @@
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+
+// How often the ROM file is checked, and how long it must stay unchanged
+// before it is read, so a file still being written by an assembler is skipped
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Notices when a ROM file changes on disk.
+pub struct RomWatcher {
+    path: String,
+    loaded: Option<(SystemTime, u64)>, // Modification time and size last reported
+    pending: Option<(SystemTime, u64)>, // A change waiting to settle
+    last_poll: Instant,
+}
+
+impl RomWatcher {
+    pub fn new(path: &str) -> RomWatcher {
+        RomWatcher {
+            path: path.to_string(),
+            loaded: stamp(path),
+            pending: None,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Whether the file has changed and stayed the same for a whole poll
+    /// interval since. Cheap to call every frame.
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        // A missing or empty file is most likely being rewritten right now
+        let current = stamp(&self.path).filter(|&(_, size)| size > 0);
+        if current.is_none() || current == self.loaded {
+            self.pending = None;
+            return false;
+        }
+
+        if current == self.pending {
+            self.loaded = current;
+            self.pending = None;
+            true
+        } else {
+            self.pending = current;
+            false
+        }
+    }
+}
+
+fn stamp(path: &str) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
@@ -0,0 +1,72 @@
+// Instructions per frame offered by the speed hotkeys
+const SPEED_STEPS: [u32; 16] = [
+    1, 2, 3, 5, 7, 8, 10, 15, 20, 30, 50, 100, 200, 500, 1000, 2000,
+];
+
+/// Runtime speed settings changed by hotkeys.
+pub struct SpeedControl {
+    pub instructions_per_frame: u32,
+    pub paused: bool,
+    pub turbo: bool,             // Run frames as fast as possible while held
+    pub advance_requested: bool, // Run a single frame while paused
+    pub game: Option<String>,    // Shown in the title instead of the emulator name
+}
+
+impl SpeedControl {
+    pub fn new(instructions_per_frame: u32) -> SpeedControl {
+        SpeedControl {
+            instructions_per_frame: instructions_per_frame.max(1),
+            paused: false,
+            turbo: false,
+            advance_requested: false,
+            game: None,
+        }
+    }
+
+    pub fn faster(&mut self) {
+        if let Some(&step) = SPEED_STEPS
+            .iter()
+            .find(|&&step| step > self.instructions_per_frame)
+        {
+            self.instructions_per_frame = step;
+        }
+    }
+
+    pub fn slower(&mut self) {
+        if let Some(&step) = SPEED_STEPS
+            .iter()
+            .rev()
+            .find(|&&step| step < self.instructions_per_frame)
+        {
+            self.instructions_per_frame = step;
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Ask for one frame to run; only takes effect while paused.
+    pub fn advance_frame(&mut self) {
+        if self.paused {
+            self.advance_requested = true;
+        }
+    }
+
+    /// Window title describing the current speed.
+    pub fn title(&self) -> String {
+        let state = if self.paused {
+            " [paused]"
+        } else if self.turbo {
+            " [turbo]"
+        } else {
+            ""
+        };
+        format!(
+            "{} - {} instructions/frame{}",
+            self.game.as_deref().unwrap_or("CHIP-8 Emulator"),
+            self.instructions_per_frame,
+            state
+        )
+    }
+}
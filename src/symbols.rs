@@ -0,0 +1,120 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+/// Labels for addresses, read from a symbol file.
+///
+/// Each line holds an address and a label, either as `0x2a0 draw_player` or
+/// `draw_player = 0x2a0`. Blank lines and lines starting with `#` are skipped.
+/// Several labels may name one address; all of them resolve, and the last one
+/// in the file is shown.
+#[derive(Clone, Default)]
+pub struct Symbols {
+    labels: BTreeMap<u16, String>,   // The label shown for each address
+    addresses: HashMap<String, u16>, // Every label by name
+}
+
+impl Symbols {
+    pub fn load(path: &str) -> Result<Symbols, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read symbols {}: {}", path, e))?;
+        Symbols::parse(&contents).map_err(|e| format!("{}:{}", path, e))
+    }
+
+    pub fn parse(contents: &str) -> Result<Symbols, String> {
+        let mut labels = BTreeMap::new();
+        let mut addresses = HashMap::new();
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (label, address) = match line.split_once('=') {
+                Some((label, address)) => (label.trim(), address.trim()),
+                None => {
+                    let mut parts = line.split_whitespace();
+                    let address = parts.next().unwrap_or_default();
+                    (parts.next().unwrap_or_default(), address)
+                }
+            };
+            let address = parse_address(address).ok_or(format!(
+                "{}: invalid address {}",
+                line_number + 1,
+                address
+            ))?;
+            if label.is_empty() || label.contains(char::is_whitespace) {
+                return Err(format!("{}: expected `address label`", line_number + 1));
+            }
+
+            labels.insert(address, label.to_string());
+            addresses.insert(label.to_string(), address);
+        }
+
+        Ok(Symbols { labels, addresses })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    /// Address of the first label after `address`.
+    pub fn next_label(&self, address: u16) -> Option<u16> {
+        let (&next, _) = self.labels.range(address.checked_add(1)?..).next()?;
+        Some(next)
+    }
+
+    /// An address relative to the nearest label at or before it, like
+    /// `draw_player+4`.
+    pub fn location(&self, address: u16) -> Option<String> {
+        let (&start, label) = self.labels.range(..=address).next_back()?;
+        Some(match address - start {
+            0 => label.clone(),
+            offset => format!("{}+{}", label, offset),
+        })
+    }
+
+    /// The location of an address, or the address in hex without a label.
+    pub fn name(&self, address: u16) -> String {
+        self.location(address)
+            .unwrap_or_else(|| format!("{:04X}", address))
+    }
+
+    /// An address given as a number, a label or a label plus an offset.
+    pub fn resolve(&self, value: &str) -> Option<u16> {
+        if let Some(&address) = self.addresses.get(value) {
+            return Some(address);
+        }
+        if let Some((label, offset)) = value.rsplit_once('+')
+            && let Some(&address) = self.addresses.get(label)
+        {
+            return address.checked_add(parse_address(offset)?);
+        }
+        parse_address(value)
+    }
+}
+
+fn parse_address(value: &str) -> Option<u16> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_sharing_an_address_all_resolve() {
+        let symbols = Symbols::parse("0x200 main\nloop = 0x200\n0x20a draw").unwrap();
+        assert_eq!(symbols.resolve("main"), Some(0x200));
+        assert_eq!(symbols.resolve("loop+2"), Some(0x202));
+        assert_eq!(symbols.name(0x204), "loop+4");
+        assert_eq!(symbols.name(0x20a), "draw");
+    }
+}
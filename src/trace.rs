@@ -0,0 +1,464 @@
+//! Execution traces: one entry per executed instruction, written as text
+//! lines or compact binary records.
+//!
+//! A text line holds the cycle count, PC, opcode, mnemonic, registers as they
+//! were before the instruction ran, and the memory it wrote:
+//!
+//! ```text
+//!         12 PC=0214 OP=F233 LD B, V2         V=0A000F00000000000000000000000001 I=0300 SP=1 DT=00 ST=00 W=0300:00,0301:01,0302:05
+//! ```
+//!
+//! With symbols the PC's label, like `show_score+6:`, comes before the mnemonic.
+//!
+//! Binary traces start with `C8T1` and hold the same fields as little-endian
+//! records of fixed size followed by the writes.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::ops::RangeInclusive;
+
+use crate::REGISTERS_V;
+use crate::chip8::Registers;
+use crate::disassembler;
+use crate::symbols::Symbols;
+
+const BINARY_MAGIC: &[u8; 4] = b"C8T1";
+// Cycle, PC, opcode, V0..VF, I, SP, DT, ST and the number of writes
+const BINARY_RECORD_SIZE: usize = 8 + 2 + 2 + REGISTERS_V + 2 + 3 + 1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum TraceFormat {
+    #[default]
+    Text,
+    Binary,
+}
+
+impl TraceFormat {
+    pub fn parse(name: &str) -> Result<TraceFormat, String> {
+        match name {
+            "text" => Ok(TraceFormat::Text),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(format!("Unknown trace format {} (text, binary)", name)),
+        }
+    }
+}
+
+/// Which instructions make it into a trace.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct TraceFilter {
+    pub pc: Option<RangeInclusive<u16>>, // Only instructions at these addresses
+    pub cycles: Option<RangeInclusive<u64>>, // Only this window of cycles
+    pub after: Option<u16>,              // Start once this address is executed
+}
+
+impl TraceFilter {
+    /// Parse comma separated settings, e.g. `pc=0x200-0x2ff,cycles=1000-2000,after=0x2a4`.
+    /// Addresses can also be labels from `symbols`, like `after=draw_player`.
+    pub fn parse(spec: &str, symbols: &Symbols) -> Result<TraceFilter, String> {
+        let mut filter = TraceFilter::default();
+
+        for part in spec.split(',').map(str::trim) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or(format!("Trace filter setting {} must be key=value", part))?;
+            let address = |value: &str| {
+                symbols.resolve(value).ok_or(format!(
+                    "Invalid address or unknown label {} for {}",
+                    value, key
+                ))
+            };
+            let number = |value: &str| {
+                parse_number(value).ok_or(format!("Invalid number {} for {}", value, key))
+            };
+            // Labels may contain dashes, so try each one as the separator
+            let range = |value: &str| {
+                value
+                    .match_indices('-')
+                    .find_map(|(index, _)| {
+                        let start = address(&value[..index]).ok()?;
+                        Some(start..=address(&value[index + 1..]).ok()?)
+                    })
+                    .ok_or(format!("{} expects a range like 0x200-0x2ff", key))
+            };
+
+            match key {
+                "pc" => filter.pc = Some(range(value)?),
+                "cycles" => {
+                    let (start, end) = value
+                        .split_once('-')
+                        .ok_or(format!("{} expects a range like 1000-2000", key))?;
+                    filter.cycles = Some(number(start)?..=number(end)?);
+                }
+                "after" => filter.after = Some(address(value)?),
+                _ => {
+                    return Err(format!(
+                        "Unknown trace filter setting {} (pc, cycles, after)",
+                        key
+                    ));
+                }
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+/// One executed instruction.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceEntry {
+    pub cycle: u64, // Instructions executed before this one
+    pub opcode: u16,
+    pub registers: Registers,   // As they were before the instruction ran
+    pub writes: Vec<(u16, u8)>, // Memory the instruction wrote: address and value
+}
+
+impl TraceEntry {
+    pub fn to_line(&self, symbols: &Symbols) -> String {
+        let r = &self.registers;
+        let v: String = r.v.iter().map(|value| format!("{:02X}", value)).collect();
+        let location = match symbols.location(r.pc) {
+            Some(location) => format!("{:<20} ", location + ":"),
+            None if symbols.is_empty() => String::new(),
+            None => format!("{:<20} ", ""),
+        };
+        let mut line = format!(
+            "{:>10} PC={:04X} OP={:04X} {}{:<16} V={} I={:04X} SP={:X} DT={:02X} ST={:02X}",
+            self.cycle,
+            r.pc,
+            self.opcode,
+            location,
+            disassembler::mnemonic(self.opcode, symbols),
+            v,
+            r.i,
+            r.sp,
+            r.dt,
+            r.st
+        );
+        if !self.writes.is_empty() {
+            let writes: Vec<String> = self
+                .writes
+                .iter()
+                .map(|(address, value)| format!("{:04X}:{:02X}", address, value))
+                .collect();
+            line.push_str(&format!(" W={}", writes.join(",")));
+        }
+        line
+    }
+
+    /// Parse a text line. Tokens without `=` are the mnemonic and are ignored,
+    /// as are fields other emulators' traces may add.
+    pub fn parse_line(line: &str) -> Result<TraceEntry, String> {
+        let mut tokens = line.split_whitespace();
+        let cycle = tokens
+            .next()
+            .and_then(|cycle| cycle.parse().ok())
+            .ok_or("Trace line doesn't start with a cycle count")?;
+
+        let mut entry = TraceEntry {
+            cycle,
+            opcode: 0,
+            registers: Registers {
+                v: [0; REGISTERS_V],
+                i: 0,
+                pc: 0,
+                sp: 0,
+                dt: 0,
+                st: 0,
+            },
+            writes: Vec::new(),
+        };
+        let hex = |value: &str| {
+            u16::from_str_radix(value, 16).map_err(|_| format!("Invalid hex number {}", value))
+        };
+        let byte = |value: &str| {
+            u8::from_str_radix(value, 16).map_err(|_| format!("Invalid hex byte {}", value))
+        };
+
+        for (key, value) in tokens.filter_map(|token| token.split_once('=')) {
+            let registers = &mut entry.registers;
+            match key {
+                "PC" => registers.pc = hex(value)?,
+                "OP" => entry.opcode = hex(value)?,
+                "I" => registers.i = hex(value)?,
+                "SP" => registers.sp = byte(value)?,
+                "DT" => registers.dt = byte(value)?,
+                "ST" => registers.st = byte(value)?,
+                "V" => {
+                    if value.len() != REGISTERS_V * 2 {
+                        return Err(format!("V= needs {} hex digits", REGISTERS_V * 2));
+                    }
+                    for (register, index) in registers.v.iter_mut().zip((0..).step_by(2)) {
+                        *register = byte(&value[index..index + 2])?;
+                    }
+                }
+                "W" => {
+                    for write in value.split(',') {
+                        let (address, value) = write
+                            .split_once(':')
+                            .ok_or(format!("Memory write {} must be address:value", write))?;
+                        entry.writes.push((hex(address)?, byte(value)?));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(entry)
+    }
+
+    fn write_binary(&self, out: &mut Vec<u8>) {
+        let r = &self.registers;
+        out.extend_from_slice(&self.cycle.to_le_bytes());
+        out.extend_from_slice(&r.pc.to_le_bytes());
+        out.extend_from_slice(&self.opcode.to_le_bytes());
+        out.extend_from_slice(&r.v);
+        out.extend_from_slice(&r.i.to_le_bytes());
+        out.extend_from_slice(&[r.sp, r.dt, r.st, self.writes.len() as u8]);
+        for (address, value) in &self.writes {
+            out.extend_from_slice(&address.to_le_bytes());
+            out.push(*value);
+        }
+    }
+
+    /// Read one binary record, returning it and the bytes after it.
+    fn read_binary(data: &[u8]) -> Result<(TraceEntry, &[u8]), String> {
+        const TRUNCATED: &str = "Binary trace ends in the middle of a record";
+
+        let record = data.get(..BINARY_RECORD_SIZE).ok_or(TRUNCATED)?;
+        let u16_at = |offset: usize| u16::from_le_bytes([record[offset], record[offset + 1]]);
+        let mut cycle = [0; 8];
+        cycle.copy_from_slice(&record[..8]);
+        let mut v = [0; REGISTERS_V];
+        v.copy_from_slice(&record[12..12 + REGISTERS_V]);
+        let rest = &record[12 + REGISTERS_V..];
+
+        let write_count = rest[5] as usize;
+        let writes = data
+            .get(BINARY_RECORD_SIZE..BINARY_RECORD_SIZE + write_count * 3)
+            .ok_or(TRUNCATED)?;
+
+        let entry = TraceEntry {
+            cycle: u64::from_le_bytes(cycle),
+            opcode: u16_at(10),
+            registers: Registers {
+                v,
+                i: u16::from_le_bytes([rest[0], rest[1]]),
+                pc: u16_at(8),
+                sp: rest[2],
+                dt: rest[3],
+                st: rest[4],
+            },
+            writes: writes
+                .chunks_exact(3)
+                .map(|write| (u16::from_le_bytes([write[0], write[1]]), write[2]))
+                .collect(),
+        };
+        Ok((entry, &data[BINARY_RECORD_SIZE + writes.len()..]))
+    }
+}
+
+/// Read a text or binary trace file.
+pub fn read_trace(path: &str) -> Result<Vec<TraceEntry>, String> {
+    let data = fs::read(path).map_err(|e| format!("Could not read trace {}: {}", path, e))?;
+
+    let mut entries = Vec::new();
+    if let Some(mut rest) = data.strip_prefix(BINARY_MAGIC.as_slice()) {
+        while !rest.is_empty() {
+            let (entry, remaining) =
+                TraceEntry::read_binary(rest).map_err(|e| format!("{}: {}", path, e))?;
+            entries.push(entry);
+            rest = remaining;
+        }
+        return Ok(entries);
+    }
+
+    let text = String::from_utf8(data).map_err(|_| format!("{} is not a trace file", path))?;
+    for (line_number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = TraceEntry::parse_line(line)
+            .map_err(|e| format!("{}:{}: {}", path, line_number + 1, e))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Writes the instructions a `Chip8` executes to a trace file.
+pub struct Tracer {
+    out: BufWriter<File>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    symbols: Symbols,       // Labels for text lines
+    triggered: bool,        // The `after` address has been reached
+    recording: bool,        // The current instruction is being traced
+    writes: Vec<(u16, u8)>, // Memory written by the current instruction
+    buffer: Vec<u8>,        // Reused for each binary record
+    error: Option<String>,  // First write error; tracing stops after it
+}
+
+impl Tracer {
+    pub fn create(
+        path: &str,
+        format: TraceFormat,
+        filter: TraceFilter,
+        symbols: Symbols,
+    ) -> Result<Tracer, String> {
+        let file =
+            File::create(path).map_err(|e| format!("Could not create trace {}: {}", path, e))?;
+        let mut out = BufWriter::new(file);
+        if format == TraceFormat::Binary {
+            out.write_all(BINARY_MAGIC)
+                .map_err(|e| format!("Could not write trace {}: {}", path, e))?;
+        }
+
+        Ok(Tracer {
+            out,
+            format,
+            triggered: filter.after.is_none(),
+            filter,
+            symbols,
+            recording: false,
+            writes: Vec::new(),
+            buffer: Vec::new(),
+            error: None,
+        })
+    }
+
+    /// Decide whether the instruction about to run at `pc` is traced.
+    pub fn begin(&mut self, cycle: u64, pc: u16) -> bool {
+        self.triggered |= self.filter.after == Some(pc);
+        self.recording = self.error.is_none()
+            && self.triggered
+            && self
+                .filter
+                .pc
+                .as_ref()
+                .is_none_or(|range| range.contains(&pc))
+            && self
+                .filter
+                .cycles
+                .as_ref()
+                .is_none_or(|range| range.contains(&cycle));
+        self.writes.clear();
+        self.recording
+    }
+
+    /// Note a byte written by the instruction being traced.
+    pub fn note_write(&mut self, address: usize, value: u8) {
+        if self.recording {
+            self.writes.push((address as u16, value));
+        }
+    }
+
+    /// Write the entry for the instruction that `begin` accepted.
+    pub fn record(&mut self, cycle: u64, opcode: u16, registers: Registers) {
+        self.recording = false;
+        let entry = TraceEntry {
+            cycle,
+            opcode,
+            registers,
+            writes: std::mem::take(&mut self.writes),
+        };
+
+        let result = match self.format {
+            TraceFormat::Text => writeln!(self.out, "{}", entry.to_line(&self.symbols)),
+            TraceFormat::Binary => {
+                self.buffer.clear();
+                entry.write_binary(&mut self.buffer);
+                self.out.write_all(&self.buffer)
+            }
+        };
+        if let Err(e) = result {
+            self.error = Some(e.to_string());
+        }
+        self.writes = entry.writes;
+    }
+
+    /// Flush the trace, reporting any error that stopped it.
+    pub fn finish(mut self) -> Result<(), String> {
+        if let Some(e) = self.error.take() {
+            return Err(format!("Could not write trace: {}", e));
+        }
+        self.out
+            .flush()
+            .map_err(|e| format!("Could not write trace: {}", e))
+    }
+}
+
+fn parse_number(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(cycle: u64, writes: Vec<(u16, u8)>) -> TraceEntry {
+        let mut v = [0; REGISTERS_V];
+        v[2] = 0x0F;
+        TraceEntry {
+            cycle,
+            opcode: 0xF233,
+            registers: Registers {
+                v,
+                i: 0x300,
+                pc: 0x214,
+                sp: 1,
+                dt: 0x3C,
+                st: 0,
+            },
+            writes,
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let entry = entry(12, vec![(0x300, 0), (0x301, 1), (0x302, 5)]);
+        let line = entry.to_line(&Symbols::default());
+        assert_eq!(TraceEntry::parse_line(&line), Ok(entry));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let entries = [entry(0, Vec::new()), entry(1, vec![(0xFFF, 0xAB)])];
+        let mut data = Vec::new();
+        for entry in &entries {
+            entry.write_binary(&mut data);
+        }
+
+        let (first, rest) = TraceEntry::read_binary(&data).unwrap();
+        let (second, rest) = TraceEntry::read_binary(rest).unwrap();
+        assert_eq!([first, second], entries);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn text_matches_binary() {
+        let original = entry(7, vec![(0x300, 9)]);
+        let mut data = Vec::new();
+        original.write_binary(&mut data);
+        let (binary, _) = TraceEntry::read_binary(&data).unwrap();
+        let text = TraceEntry::parse_line(&original.to_line(&Symbols::default())).unwrap();
+        assert_eq!(binary, text);
+    }
+
+    #[test]
+    fn truncated_binary_records() {
+        let mut data = Vec::new();
+        entry(3, vec![(0x300, 1), (0x301, 2)]).write_binary(&mut data);
+
+        // Cut in the fixed part and in the writes
+        assert!(TraceEntry::read_binary(&data[..BINARY_RECORD_SIZE - 1]).is_err());
+        assert!(TraceEntry::read_binary(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn unknown_fields_are_skipped() {
+        let line = entry(5, Vec::new()).to_line(&Symbols::default()) + " LAT=3 X=1";
+        assert_eq!(TraceEntry::parse_line(&line), Ok(entry(5, Vec::new())));
+    }
+}
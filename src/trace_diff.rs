@@ -0,0 +1,273 @@
+//! Find where two execution traces part ways.
+
+use crate::symbols::Symbols;
+use crate::trace::TraceEntry;
+
+// Fields that can be left out of the comparison, e.g. when a reference
+// emulator doesn't log memory writes
+const FIELDS: [&str; 8] = ["cycle", "pc", "opcode", "v", "i", "sp", "timers", "writes"];
+
+/// Fields to compare, all of them unless some are ignored.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Comparison {
+    ignored: [bool; FIELDS.len()],
+}
+
+impl Comparison {
+    pub fn all() -> Comparison {
+        Comparison {
+            ignored: [false; FIELDS.len()],
+        }
+    }
+
+    fn ignores(&self, field: &str) -> bool {
+        let index = FIELDS
+            .iter()
+            .position(|&f| f == field)
+            .expect("compared fields are listed in FIELDS");
+        self.ignored[index]
+    }
+
+    /// Parse a comma separated list of fields to ignore, e.g. `timers,writes`.
+    pub fn ignoring(spec: &str) -> Result<Comparison, String> {
+        let mut comparison = Comparison::all();
+        for name in spec.split(',').map(str::trim) {
+            let index = FIELDS
+                .iter()
+                .position(|&field| field == name)
+                .ok_or(format!(
+                    "Unknown trace field {} ({})",
+                    name,
+                    FIELDS.join(", ")
+                ))?;
+            comparison.ignored[index] = true;
+        }
+        Ok(comparison)
+    }
+
+    /// Differences between two entries, one line each.
+    pub fn differences(&self, a: &TraceEntry, b: &TraceEntry) -> Vec<String> {
+        let (ra, rb) = (&a.registers, &b.registers);
+        let mut differences = Vec::new();
+        let mut compare = |field: &str, name: String, a: String, b: String| {
+            if !self.ignores(field) && a != b {
+                differences.push(format!("{}: {} vs {}", name, a, b));
+            }
+        };
+
+        compare(
+            "cycle",
+            "cycle".into(),
+            a.cycle.to_string(),
+            b.cycle.to_string(),
+        );
+        compare(
+            "pc",
+            "PC".into(),
+            hex(ra.pc as u32, 4),
+            hex(rb.pc as u32, 4),
+        );
+        compare(
+            "opcode",
+            "opcode".into(),
+            hex(a.opcode as u32, 4),
+            hex(b.opcode as u32, 4),
+        );
+        for (register, (va, vb)) in ra.v.iter().zip(&rb.v).enumerate() {
+            compare(
+                "v",
+                format!("V{:X}", register),
+                hex(*va as u32, 2),
+                hex(*vb as u32, 2),
+            );
+        }
+        compare("i", "I".into(), hex(ra.i as u32, 4), hex(rb.i as u32, 4));
+        compare("sp", "SP".into(), ra.sp.to_string(), rb.sp.to_string());
+        compare(
+            "timers",
+            "DT".into(),
+            hex(ra.dt as u32, 2),
+            hex(rb.dt as u32, 2),
+        );
+        compare(
+            "timers",
+            "ST".into(),
+            hex(ra.st as u32, 2),
+            hex(rb.st as u32, 2),
+        );
+        compare("writes", "writes".into(), writes(a), writes(b));
+
+        differences
+    }
+}
+
+/// The first entry where two traces disagree.
+pub struct Divergence {
+    pub index: (usize, usize),    // Entry number in each trace
+    pub differences: Vec<String>, // What differs; empty when one trace ended
+}
+
+/// Compare two traces entry by entry. Unless cycles are ignored, a trace
+/// that starts at a later cycle than the other, e.g. because it was recorded
+/// with a filter, is compared from the first cycle both have.
+pub fn first_divergence(
+    a: &[TraceEntry],
+    b: &[TraceEntry],
+    comparison: &Comparison,
+) -> Option<Divergence> {
+    let start = match (a.first(), b.first()) {
+        (Some(first_a), Some(first_b)) if !comparison.ignores("cycle") => {
+            let cycle = first_a.cycle.max(first_b.cycle);
+            let from = |trace: &[TraceEntry]| trace.iter().take_while(|e| e.cycle < cycle).count();
+            (from(a), from(b))
+        }
+        _ => (0, 0),
+    };
+
+    let (a, b) = (&a[start.0..], &b[start.1..]);
+    let common = a.len().min(b.len());
+    (0..common)
+        .find_map(|index| {
+            let differences = comparison.differences(&a[index], &b[index]);
+            (!differences.is_empty()).then_some((index, differences))
+        })
+        .or_else(|| (a.len() != b.len()).then_some((common, Vec::new())))
+        .map(|(index, differences)| Divergence {
+            index: (start.0 + index, start.1 + index),
+            differences,
+        })
+}
+
+/// Describe a divergence with `context` entries of each trace around it.
+pub fn report(
+    a: &[TraceEntry],
+    b: &[TraceEntry],
+    names: (&str, &str),
+    divergence: &Divergence,
+    context: usize,
+    symbols: &Symbols,
+) -> String {
+    let (index_a, index_b) = divergence.index;
+    let mut lines = Vec::new();
+
+    match (a.get(index_a), b.get(index_b)) {
+        (Some(entry), Some(_)) if index_a == index_b => lines.push(format!(
+            "Traces diverge at entry {} (cycle {}, PC {}):",
+            index_a + 1,
+            entry.cycle,
+            symbols.name(entry.registers.pc)
+        )),
+        (Some(entry), Some(_)) => lines.push(format!(
+            "Traces diverge at entries {} and {} (cycle {}, PC {}):",
+            index_a + 1,
+            index_b + 1,
+            entry.cycle,
+            symbols.name(entry.registers.pc)
+        )),
+        (Some(_), None) => lines.push(format!("{} ends after {} entries", names.1, index_b)),
+        _ => lines.push(format!("{} ends after {} entries", names.0, index_a)),
+    }
+    for difference in &divergence.differences {
+        lines.push(format!("  {}", difference));
+    }
+
+    lines.push(String::new());
+    for entry in &a[index_a.saturating_sub(context)..index_a] {
+        lines.push(format!("  {}", entry.to_line(symbols)));
+    }
+    for (marker, trace, index) in [("<", a, index_a), (">", b, index_b)] {
+        for entry in trace.iter().skip(index).take(context + 1) {
+            lines.push(format!("{} {}", marker, entry.to_line(symbols)));
+        }
+    }
+    lines.push(format!("(< {}, > {})", names.0, names.1));
+
+    lines.join("\n")
+}
+
+fn hex(value: u32, digits: usize) -> String {
+    format!("{:0digits$X}", value, digits = digits)
+}
+
+fn writes(entry: &TraceEntry) -> String {
+    let writes: Vec<String> = entry
+        .writes
+        .iter()
+        .map(|(address, value)| format!("{:04X}:{:02X}", address, value))
+        .collect();
+    if writes.is_empty() {
+        "none".to_string()
+    } else {
+        writes.join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::REGISTERS_V;
+    use crate::chip8::Registers;
+
+    /// A trace running `0200: ADD V0, 1` and `0202: JP 0x200` from `start`.
+    fn trace(start: u64, length: u64) -> Vec<TraceEntry> {
+        (start..start + length)
+            .map(|cycle| TraceEntry {
+                cycle,
+                opcode: if cycle % 2 == 0 { 0x7001 } else { 0x1200 },
+                registers: Registers {
+                    v: [(cycle / 2) as u8; REGISTERS_V],
+                    i: 0,
+                    pc: 0x200 + (cycle % 2) as u16 * 2,
+                    sp: 0,
+                    dt: 0,
+                    st: 0,
+                },
+                writes: Vec::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_traces_match() {
+        assert!(first_divergence(&trace(0, 10), &trace(0, 10), &Comparison::all()).is_none());
+    }
+
+    #[test]
+    fn one_trace_starts_later() {
+        let (a, b) = (trace(0, 10), trace(4, 6));
+        assert!(first_divergence(&a, &b, &Comparison::all()).is_none());
+        assert!(first_divergence(&b, &a, &Comparison::all()).is_none());
+
+        let mut b = b;
+        b[3].registers.i = 0x300;
+        let divergence = first_divergence(&a, &b, &Comparison::all()).unwrap();
+        assert_eq!(divergence.index, (7, 3));
+        assert_eq!(divergence.differences, ["I: 0000 vs 0300"]);
+    }
+
+    #[test]
+    fn one_trace_ends_early() {
+        let divergence = first_divergence(&trace(0, 10), &trace(0, 6), &Comparison::all()).unwrap();
+        assert_eq!(divergence.index, (6, 6));
+        assert!(divergence.differences.is_empty());
+    }
+
+    #[test]
+    fn ignored_fields() {
+        let a = trace(0, 4);
+        let mut b = trace(0, 4);
+        b[1].registers.dt = 5;
+        b[2].writes.push((0x300, 1));
+
+        let comparison = Comparison::ignoring("timers,writes").unwrap();
+        assert!(first_divergence(&a, &b, &comparison).is_none());
+        let divergence = first_divergence(&a, &b, &Comparison::all()).unwrap();
+        assert_eq!(divergence.index, (1, 1));
+        assert_eq!(divergence.differences, ["DT: 00 vs 05"]);
+
+        // Without cycles to align on, a later start is a difference
+        let comparison = Comparison::ignoring("cycle").unwrap();
+        assert!(first_divergence(&trace(0, 4), &trace(2, 2), &comparison).is_some());
+        assert!(Comparison::ignoring("cycles").is_err());
+    }
+}
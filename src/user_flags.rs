@@ -0,0 +1,46 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::data_dir::user_data_dir;
+use crate::rom_database::rom_hash;
+
+/// Where a ROM's SUPER-CHIP RPL user flags (Fx75/Fx85) are kept, so high
+/// scores survive restarts like they did on the HP-48.
+pub struct UserFlagFile {
+    path: Option<PathBuf>, // None when there is nowhere to keep them
+}
+
+impl UserFlagFile {
+    /// The flag file for a ROM, named after its hash in `dir` or the user
+    /// data directory.
+    pub fn for_rom(dir: Option<&str>, rom: &[u8]) -> UserFlagFile {
+        let dir = dir
+            .map(PathBuf::from)
+            .or_else(|| user_data_dir().map(|dir| dir.join("flags")));
+        UserFlagFile {
+            path: dir.map(|dir| dir.join(format!("{}.flags", rom_hash(rom)))),
+        }
+    }
+
+    /// Saved flags, or zeros when there are none yet, sized to `count`.
+    pub fn load(&self, count: usize) -> Vec<u8> {
+        let mut flags = self
+            .path
+            .as_ref()
+            .and_then(|path| fs::read(path).ok())
+            .unwrap_or_default();
+        flags.resize(count, 0);
+        flags
+    }
+
+    pub fn save(&self, flags: &[u8]) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Err("No user data directory to save flags in".to_string());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+        fs::write(path, flags).map_err(|e| format!("Could not save {}: {}", path.display(), e))
+    }
+}